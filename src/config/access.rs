@@ -0,0 +1,77 @@
+use crate::config::{tokenize, Importable};
+use crate::util::ip;
+use anyhow::anyhow;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+#[derive(Clone, Debug)]
+pub struct AccessRule {
+    pub allow: bool,
+    /// `None` for `all`
+    pub nets: Option<Vec<IpNet>>,
+}
+
+/// Allow and deny rules, the first matched one decides.
+#[derive(Clone, Debug)]
+pub struct Access {
+    pub rules: Vec<AccessRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum AccessRaw {
+    List(Vec<String>),
+    Table { rules: Vec<String> },
+}
+
+impl AccessRule {
+    /// `allow 10.0.0.0/8`, `deny 192.0.2.1 2001:db8::/32` or `deny all`.
+    pub fn new(rule: &str, path: &str) -> anyhow::Result<Self> {
+        let parts =
+            tokenize(rule).map_err(|err| anyhow!("{} Wrong syntax: {}, {}", path, rule, err))?;
+        let allow = match parts.first().map(|p| p.as_str()) {
+            Some("allow") => true,
+            Some("deny") => false,
+            _ => Err(anyhow!("{} Wrong syntax: {}", path, rule))?,
+        };
+        let nets = match &parts[1..] {
+            [] => Err(anyhow!("{} Wrong syntax: {}", path, rule))?,
+            [all] if all == "all" => None,
+            nets => Some(ip::parse_nets(nets, path)?),
+        };
+        Ok(Self { allow, nets })
+    }
+
+    fn is_match(&self, client_ip: Option<IpAddr>) -> bool {
+        match (&self.nets, client_ip) {
+            (None, _) => true,
+            (Some(nets), Some(client_ip)) => ip::contains(nets, &client_ip),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl Access {
+    pub fn from_raw(raw: Importable<AccessRaw>, path: &str) -> anyhow::Result<Self> {
+        let (raw, path) = raw.import(path)?;
+        let rules = match raw {
+            AccessRaw::List(rules) => rules,
+            AccessRaw::Table { rules } => rules,
+        };
+        Ok(Self {
+            rules: rules
+                .iter()
+                .map(|rule| AccessRule::new(rule, &path))
+                .collect::<anyhow::Result<Vec<AccessRule>>>()?,
+        })
+    }
+
+    /// Requests not matching any rule are allowed.
+    pub fn is_allowed(&self, client_ip: Option<IpAddr>) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.is_match(client_ip))
+            .is_none_or(|rule| rule.allow)
+    }
+}
@@ -0,0 +1,43 @@
+use crate::util::htpasswd::UserFile;
+use crate::util::path;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct AuthBasic {
+    pub realm: String,
+    pub user_file: Arc<UserFile>,
+    /// remove `Authorization` before proxying
+    pub strip_authorization: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AuthBasicRaw {
+    pub realm: Option<String>,
+    pub user_file: String,
+    pub strip_authorization: Option<bool>,
+}
+
+impl AuthBasic {
+    pub fn from_raw(raw: AuthBasicRaw, path: &str) -> anyhow::Result<Self> {
+        let realm = raw.realm.unwrap_or(String::from("Restricted"));
+        if realm.contains(['"', '\\']) || realm.chars().any(|c| c.is_control()) {
+            Err(anyhow!(
+                "{} Wrong syntax: auth_basic.realm = {}",
+                path,
+                realm
+            ))?;
+        }
+        Ok(Self {
+            realm,
+            user_file: Arc::new(UserFile::load(path::resolve(path, &raw.user_file))?),
+            strip_authorization: raw.strip_authorization.unwrap_or_default(),
+        })
+    }
+
+    /// Value of `WWW-Authenticate`.
+    pub fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)
+    }
+}
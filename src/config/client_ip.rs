@@ -0,0 +1,98 @@
+use crate::util::ip;
+use anyhow::anyhow;
+use http::HeaderName;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// PROXY protocol accepted from the peers in the networks, other peers are served as without it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProxyProtocol {
+    Off,
+    /// accept connections with or without a PROXY header
+    Optional(Vec<IpNet>),
+    Required(Vec<IpNet>),
+}
+
+impl ProxyProtocol {
+    pub fn from_raw(
+        raw: Option<String>,
+        from: Option<Vec<String>>,
+        path: &str,
+    ) -> anyhow::Result<Self> {
+        let from = || match &from {
+            None => Err(anyhow!(
+                "{} Wrong syntax: proxy_protocol_from is required with proxy_protocol",
+                path
+            )),
+            Some(from) => ip::parse_nets(from, path),
+        };
+        match raw.as_deref() {
+            None | Some("off") => Ok(ProxyProtocol::Off),
+            Some("optional") => Ok(ProxyProtocol::Optional(from()?)),
+            Some("required") => Ok(ProxyProtocol::Required(from()?)),
+            Some(p) => Err(anyhow!("{} Wrong syntax: proxy_protocol = {}", path, p)),
+        }
+    }
+
+    /// Whether the peer may send a PROXY header.
+    pub fn trusts(&self, peer: &IpAddr) -> bool {
+        match self {
+            ProxyProtocol::Off => false,
+            ProxyProtocol::Optional(from) | ProxyProtocol::Required(from) => {
+                ip::contains(from, &peer.to_canonical())
+            }
+        }
+    }
+}
+
+/// Version of PROXY protocol sent to upstream services.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+impl ProxyProtocolVersion {
+    pub fn from_raw(raw: Option<String>, path: &str) -> anyhow::Result<Option<Self>> {
+        match raw.as_deref() {
+            None | Some("off") => Ok(None),
+            Some("v1") => Ok(Some(ProxyProtocolVersion::V1)),
+            Some("v2") => Ok(Some(ProxyProtocolVersion::V2)),
+            Some(v) => Err(anyhow!(
+                "{} Wrong syntax: send_proxy_protocol = {}",
+                path,
+                v
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RealIp {
+    pub header: HeaderName,
+    /// peers allowed to set the header
+    pub from: Vec<IpNet>,
+}
+
+impl RealIp {
+    pub fn from_raw(
+        header: Option<String>,
+        from: Option<Vec<String>>,
+        path: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let header = match header {
+            None => return Ok(None),
+            Some(header) => HeaderName::from_str(&header)
+                .map_err(|_| anyhow!("{} Wrong syntax: real_ip_header = {}", path, header))?,
+        };
+        let from = match from {
+            None => Err(anyhow!(
+                "{} Wrong syntax: real_ip_from is required with real_ip_header",
+                path
+            ))?,
+            Some(from) => ip::parse_nets(&from, path)?,
+        };
+        Ok(Some(Self { header, from }))
+    }
+}
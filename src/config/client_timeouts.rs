@@ -0,0 +1,73 @@
+use anyhow::anyhow;
+use std::time::Duration;
+
+/// Timeouts against slow clients, unset ones are left to pingora.
+#[derive(Clone, Debug, Default)]
+pub struct ClientTimeouts {
+    /// total time to read a request header, for every request on a connection, server only
+    pub header: Option<Duration>,
+    /// between two reads of the request body
+    pub body: Option<Duration>,
+    /// idle time of a connection between requests, zero to close after the response
+    pub keepalive: Option<Duration>,
+    /// requests served on a connection, server only
+    pub keepalive_requests: Option<u32>,
+    /// between two writes of the response
+    pub send: Option<Duration>,
+}
+
+fn positive(value: Option<u64>, name: &str, path: &str) -> anyhow::Result<Option<Duration>> {
+    match value {
+        Some(0) => Err(anyhow!(
+            "{} Wrong syntax: {} should be positive",
+            path,
+            name
+        )),
+        value => Ok(value.map(Duration::from_millis)),
+    }
+}
+
+impl ClientTimeouts {
+    pub fn from_raw(
+        header: Option<u64>,
+        body: Option<u64>,
+        keepalive: Option<u64>,
+        keepalive_requests: Option<u32>,
+        send: Option<u64>,
+        path: &str,
+    ) -> anyhow::Result<Self> {
+        if keepalive_requests == Some(0) {
+            Err(anyhow!(
+                "{} Wrong syntax: keepalive_requests should be positive",
+                path
+            ))?;
+        }
+        Ok(Self {
+            header: positive(header, "client_header_timeout", path)?,
+            body: positive(body, "client_body_timeout", path)?,
+            keepalive: keepalive.map(Duration::from_millis),
+            keepalive_requests,
+            send: positive(send, "send_timeout", path)?,
+        })
+    }
+
+    /// Timeouts of a source over the ones of the server.
+    pub fn merge(&self, source: &ClientTimeouts) -> Self {
+        Self {
+            header: self.header,
+            body: source.body.or(self.body),
+            keepalive: source.keepalive.or(self.keepalive),
+            keepalive_requests: self.keepalive_requests,
+            send: source.send.or(self.send),
+        }
+    }
+}
+
+/// Keepalive in seconds for pingora, `None` to close the connection.
+pub fn keepalive_secs(timeout: Duration) -> Option<u64> {
+    match timeout.as_millis() {
+        0 => None,
+        // pingora takes 0 as no timeout
+        ms => Some(ms.div_ceil(1000) as u64),
+    }
+}
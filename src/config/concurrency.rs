@@ -0,0 +1,45 @@
+use crate::util::concurrency::Limiter;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Caps on in-flight requests.
+#[derive(Clone, Debug)]
+pub struct Concurrency {
+    pub max: Option<usize>,
+    pub per_ip: Option<usize>,
+    /// time to wait for a slot before 503, zero to reject at once
+    pub queue_timeout: Duration,
+    pub limiter: Arc<Limiter>,
+}
+
+#[derive(Deserialize)]
+pub struct ConcurrencyRaw {
+    pub max: Option<usize>,
+    pub per_ip: Option<usize>,
+    pub queue_timeout: Option<u64>,
+}
+
+impl Concurrency {
+    pub fn from_raw(raw: ConcurrencyRaw, path: &str) -> anyhow::Result<Self> {
+        if raw.max.is_none() && raw.per_ip.is_none() {
+            Err(anyhow!(
+                "{} Wrong syntax: concurrency requires max or per_ip",
+                path
+            ))?;
+        }
+        if raw.max == Some(0) || raw.per_ip == Some(0) {
+            Err(anyhow!(
+                "{} Wrong syntax: concurrency limits should be positive",
+                path
+            ))?;
+        }
+        Ok(Self {
+            max: raw.max,
+            per_ip: raw.per_ip,
+            queue_timeout: Duration::from_millis(raw.queue_timeout.unwrap_or_default()),
+            limiter: Arc::new(Limiter::new(raw.max, raw.per_ip)),
+        })
+    }
+}
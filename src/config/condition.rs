@@ -0,0 +1,103 @@
+use crate::util::ip;
+use anyhow::anyhow;
+use http::{HeaderName, Method};
+use ipnet::IpNet;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Clone, Debug)]
+pub enum ValueMatch {
+    Present,
+    Equal(String),
+    Regex(Regex),
+}
+
+impl ValueMatch {
+    /// `*` for presence, `~ regex`, `= value` or a bare value to compare.
+    pub fn new(value: &str, path: &str) -> anyhow::Result<Self> {
+        if value == "*" {
+            Ok(ValueMatch::Present)
+        } else if let Some(re) = value.strip_prefix("~ ") {
+            Ok(ValueMatch::Regex(
+                Regex::new(re).map_err(|err| anyhow!("{} {}", path, err))?,
+            ))
+        } else if let Some(v) = value.strip_prefix("= ") {
+            Ok(ValueMatch::Equal(String::from(v)))
+        } else {
+            Ok(ValueMatch::Equal(String::from(value)))
+        }
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            ValueMatch::Present => true,
+            ValueMatch::Equal(v) => v == value,
+            ValueMatch::Regex(re) => re.is_match(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub method: Option<Vec<Method>>,
+    pub header: Vec<(HeaderName, ValueMatch)>,
+    pub cookie: Vec<(String, ValueMatch)>,
+    pub query: Vec<(String, ValueMatch)>,
+    pub client: Option<Vec<IpNet>>,
+}
+
+#[derive(Deserialize)]
+pub struct ConditionRaw {
+    pub method: Option<Vec<String>>,
+    pub header: Option<HashMap<String, String>>,
+    pub cookie: Option<HashMap<String, String>>,
+    pub query: Option<HashMap<String, String>>,
+    pub client: Option<Vec<String>>,
+}
+
+fn value_matches(
+    map: Option<HashMap<String, String>>,
+    path: &str,
+) -> anyhow::Result<Vec<(String, ValueMatch)>> {
+    let mut result = Vec::new();
+    for (k, v) in map.unwrap_or_default() {
+        let value = ValueMatch::new(&v, path)?;
+        result.push((k, value));
+    }
+    Ok(result)
+}
+
+impl Condition {
+    pub fn from_raw(raw: ConditionRaw, path: &str) -> anyhow::Result<Self> {
+        let method = match raw.method {
+            None => None,
+            Some(list) => Some(
+                list.iter()
+                    .map(|m| {
+                        Method::from_str(&m.to_uppercase())
+                            .map_err(|_| anyhow!("{} Wrong syntax: method = {}", path, m))
+                    })
+                    .collect::<anyhow::Result<Vec<Method>>>()?,
+            ),
+        };
+        let mut header = Vec::new();
+        for (k, v) in value_matches(raw.header, path)? {
+            let name = HeaderName::from_str(&k)
+                .map_err(|_| anyhow!("{} Wrong syntax: header {}", path, k))?;
+            header.push((name, v));
+        }
+        let client = match raw.client {
+            None => None,
+            Some(list) => Some(ip::parse_nets(&list, path)?),
+        };
+        Ok(Self {
+            method,
+            header,
+            cookie: value_matches(raw.cookie, path)?,
+            query: value_matches(raw.query, path)?,
+            client,
+        })
+    }
+}
@@ -15,6 +15,8 @@ pub struct Config {
     pub threads: Option<usize>,
     pub work_stealing: Option<bool>,
     pub ca_file: Option<String>,
+    pub server_tokens: Option<String>,
+    pub metrics: Option<String>,
     pub grace_period_seconds: Option<u64>,
     pub graceful_shutdown_timeout_seconds: Option<u64>,
     pub client_bind_to_ipv4: Option<Vec<String>>,
@@ -23,6 +25,13 @@ pub struct Config {
     pub upstream_connect_offload_thread_per_pool: Option<usize>,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ServerTokensRaw {
+    Enabled(bool),
+    Custom(String),
+}
+
 #[derive(Deserialize)]
 pub struct ConfigRaw {
     pub server: Importable<HashMap<String, Importable<ServerRaw>>>,
@@ -35,6 +44,8 @@ pub struct ConfigRaw {
     pub threads: Option<usize>,
     pub work_stealing: Option<bool>,
     pub ca_file: Option<String>,
+    pub server_tokens: Option<ServerTokensRaw>,
+    pub metrics: Option<String>,
     pub grace_period_seconds: Option<u64>,
     pub graceful_shutdown_timeout_seconds: Option<u64>,
     pub client_bind_to_ipv4: Option<Vec<String>>,
@@ -62,6 +73,13 @@ impl Config {
             threads: raw.threads,
             work_stealing: raw.work_stealing,
             ca_file: raw.ca_file,
+            server_tokens: match raw.server_tokens {
+                None | Some(ServerTokensRaw::Enabled(true)) => Some(String::from("Pingpong")),
+                Some(ServerTokensRaw::Enabled(false)) => None,
+                Some(ServerTokensRaw::Custom(tokens)) if tokens.is_empty() => None,
+                Some(ServerTokensRaw::Custom(tokens)) => Some(tokens),
+            },
+            metrics: raw.metrics,
             grace_period_seconds: raw.grace_period_seconds,
             graceful_shutdown_timeout_seconds: raw.graceful_shutdown_timeout_seconds,
             client_bind_to_ipv4: raw.client_bind_to_ipv4,
@@ -0,0 +1,104 @@
+use crate::config::{parse_header_names, ValueMatch};
+use anyhow::anyhow;
+use http::Method;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Cross-origin requests allowed by a source, preflights are answered by Pingpong.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    pub origins: Vec<ValueMatch>,
+    /// `*` in origins, answered with a literal `*`, never with credentials
+    pub any_origin: bool,
+    pub methods: Vec<Method>,
+    /// request headers allowed in lowercase, `None` for any
+    pub headers: Option<Vec<String>>,
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    /// seconds the preflight can be cached
+    pub max_age: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CorsRaw {
+    pub origins: Vec<String>,
+    pub methods: Option<Vec<String>>,
+    pub headers: Option<Vec<String>>,
+    pub expose_headers: Option<Vec<String>>,
+    pub credentials: Option<bool>,
+    pub max_age: Option<u64>,
+}
+
+/// `*` in an origin like `https://*.example.com` matches one or more labels.
+fn origin_match(origin: &str, path: &str) -> anyhow::Result<ValueMatch> {
+    if origin.starts_with("~ ") || !origin.contains('*') {
+        return ValueMatch::new(origin.trim_end_matches('/'), path);
+    }
+    let pattern = origin
+        .trim_end_matches('/')
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(r"[^/:]+");
+    Ok(ValueMatch::Regex(
+        Regex::new(&format!("^{}$", pattern))
+            .map_err(|e| anyhow!("{} Wrong syntax: cors.origins {}, {}", path, origin, e))?,
+    ))
+}
+
+impl Cors {
+    pub fn from_raw(raw: CorsRaw, path: &str) -> anyhow::Result<Self> {
+        if raw.origins.is_empty() {
+            Err(anyhow!("{} Wrong syntax: cors.origins is empty", path))?;
+        }
+        let any_origin = raw.origins.iter().any(|origin| origin == "*");
+        let credentials = raw.credentials.unwrap_or(false);
+        if any_origin && credentials {
+            Err(anyhow!(
+                "{} Wrong syntax: cors.origins * cannot be used with credentials, list the origins instead",
+                path
+            ))?;
+        }
+        let mut origins = Vec::new();
+        for origin in raw.origins.iter().filter(|origin| *origin != "*") {
+            origins.push(origin_match(origin, path)?);
+        }
+        let methods = match raw.methods {
+            Some(methods) => {
+                let mut result = Vec::new();
+                for method in methods {
+                    result.push(
+                        Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|_| {
+                            anyhow!("{} Wrong syntax: cors.methods {}", path, method)
+                        })?,
+                    );
+                }
+                result
+            }
+            None => vec![Method::GET, Method::HEAD, Method::POST],
+        };
+        let headers = match raw.headers {
+            Some(names) => Some(
+                parse_header_names(names, path)?
+                    .into_iter()
+                    .map(|name| name.to_lowercase())
+                    .collect(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            origins,
+            any_origin,
+            methods,
+            headers,
+            expose_headers: parse_header_names(raw.expose_headers.unwrap_or_default(), path)?,
+            credentials,
+            max_age: raw.max_age,
+        })
+    }
+
+    /// `null` of sandboxed pages and local files is only allowed when listed.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o.is_match(origin)) || (self.any_origin && origin != "null")
+    }
+}
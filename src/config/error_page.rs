@@ -0,0 +1,84 @@
+use crate::util::mime::get_mime_type;
+use crate::util::path;
+use anyhow::anyhow;
+use http::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Clone, Debug)]
+pub struct ErrorPage {
+    pub body: String,
+    pub content_type: String,
+    /// expand request variables in body
+    pub template: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ErrorPageRaw {
+    File(String),
+    Table {
+        file: Option<String>,
+        template: Option<String>,
+        content_type: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    pages: HashMap<StatusCode, ErrorPage>,
+}
+
+impl ErrorPage {
+    pub fn from_raw(raw: ErrorPageRaw, path: &str) -> anyhow::Result<Self> {
+        let (file, template, content_type) = match raw {
+            ErrorPageRaw::File(file) => (file, false, None),
+            ErrorPageRaw::Table {
+                file: Some(file),
+                template: None,
+                content_type,
+            } => (file, false, content_type),
+            ErrorPageRaw::Table {
+                file: None,
+                template: Some(file),
+                content_type,
+            } => (file, true, content_type),
+            ErrorPageRaw::Table { .. } => Err(anyhow!(
+                "{} Wrong syntax: error_page requires one of file and template",
+                path
+            ))?,
+        };
+        let file = path::resolve(path, &file);
+        let body = fs::read_to_string(&file).or(Err(anyhow!("Cannot read file {}", &file)))?;
+        Ok(Self {
+            body,
+            content_type: content_type.unwrap_or(get_mime_type(&file)),
+            template,
+        })
+    }
+}
+
+impl ErrorPages {
+    pub fn from_raw(raw: HashMap<String, ErrorPageRaw>, path: &str) -> anyhow::Result<Self> {
+        let mut pages = HashMap::new();
+        for (status, page) in raw {
+            let code = status
+                .parse::<u16>()
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .filter(|code| code.is_client_error() || code.is_server_error())
+                .ok_or(anyhow!(
+                    "{} Wrong syntax: error_page status {}",
+                    path,
+                    status
+                ))?;
+            pages.insert(code, ErrorPage::from_raw(page, path)?);
+        }
+        Ok(Self { pages })
+    }
+
+    pub fn get(&self, status: StatusCode) -> Option<&ErrorPage> {
+        self.pages.get(&status)
+    }
+}
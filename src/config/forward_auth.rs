@@ -0,0 +1,112 @@
+use crate::config::parse_header_names;
+use anyhow::anyhow;
+use http::Uri;
+use serde::Deserialize;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub enum AuthEndpoint {
+    /// name of a proxy source with the same sni
+    Source(String),
+    Url {
+        /// resolved when the config is loaded
+        addr: SocketAddr,
+        host: String,
+        port: u16,
+        ssl: bool,
+    },
+}
+
+/// Ask an auth service before answering the request.
+#[derive(Clone, Debug)]
+pub struct ForwardAuth {
+    pub endpoint: AuthEndpoint,
+    /// uri of the subrequest, default to the original one
+    pub uri: Option<String>,
+    /// headers sent to the auth service, `None` for all
+    pub request_headers: Option<Vec<String>>,
+    /// headers copied from the auth response to the upstream request
+    pub response_headers: Vec<String>,
+    pub timeout: Duration,
+}
+
+#[derive(Deserialize)]
+pub struct ForwardAuthRaw {
+    pub url: Option<String>,
+    pub source: Option<String>,
+    pub uri: Option<String>,
+    pub request_headers: Option<Vec<String>>,
+    pub response_headers: Option<Vec<String>>,
+    pub timeout: Option<u64>,
+}
+
+impl ForwardAuth {
+    pub fn from_raw(raw: ForwardAuthRaw, path: &str) -> anyhow::Result<Self> {
+        let (endpoint, uri) = match (raw.url, raw.source) {
+            (Some(url), None) => {
+                if raw.uri.is_some() {
+                    Err(anyhow!(
+                        "{} Wrong syntax: forward_auth.uri only works with source",
+                        path
+                    ))?;
+                }
+                let parsed = url.parse::<Uri>().map_err(|e| {
+                    anyhow!("{} Wrong syntax: forward_auth.url {}, {}", path, url, e)
+                })?;
+                let ssl = match parsed.scheme_str() {
+                    Some("http") => false,
+                    Some("https") => true,
+                    _ => Err(anyhow!(
+                        "{} Wrong syntax: forward_auth.url {} should be http or https",
+                        path,
+                        url
+                    ))?,
+                };
+                let host = match parsed.host() {
+                    Some(host) => String::from(host.trim_start_matches('[').trim_end_matches(']')),
+                    None => Err(anyhow!("{} Wrong syntax: forward_auth.url {}", path, url))?,
+                };
+                let port = parsed.port_u16().unwrap_or(if ssl { 443 } else { 80 });
+                let addr = (host.as_str(), port)
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .ok_or(anyhow!(
+                        "{} Failed to resolve forward_auth.url {}",
+                        path,
+                        url
+                    ))?;
+                let uri = parsed
+                    .path_and_query()
+                    .map(|p| String::from(p.as_str()))
+                    .unwrap_or(String::from("/"));
+                (
+                    AuthEndpoint::Url {
+                        addr,
+                        host,
+                        port,
+                        ssl,
+                    },
+                    Some(uri),
+                )
+            }
+            (None, Some(source)) => (AuthEndpoint::Source(source), raw.uri),
+            _ => Err(anyhow!(
+                "{} Wrong syntax: forward_auth requires one of url and source",
+                path
+            ))?,
+        };
+        let request_headers = match raw.request_headers {
+            Some(names) => Some(parse_header_names(names, path)?),
+            None => None,
+        };
+        Ok(Self {
+            endpoint,
+            uri,
+            request_headers,
+            response_headers: parse_header_names(raw.response_headers.unwrap_or_default(), path)?,
+            timeout: Duration::from_millis(raw.timeout.unwrap_or(5000)),
+        })
+    }
+}
@@ -0,0 +1,47 @@
+use crate::util::ip;
+use anyhow::anyhow;
+use ipnet::IpNet;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForwardedMode {
+    /// `X-Forwarded-*` and `X-Real-IP`
+    XForwarded,
+    /// RFC 7239 `Forwarded`
+    Forwarded,
+    All,
+}
+
+#[derive(Clone, Debug)]
+pub struct ForwardedHeaders {
+    pub mode: ForwardedMode,
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl ForwardedHeaders {
+    pub fn from_raw(
+        mode: Option<String>,
+        trusted_proxies: Option<Vec<String>>,
+        path: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let mode = match mode.as_deref().map(|m| m.to_lowercase()).as_deref() {
+            None | Some("off") => return Ok(None),
+            Some("x-forwarded") => ForwardedMode::XForwarded,
+            Some("forwarded") => ForwardedMode::Forwarded,
+            Some("all") => ForwardedMode::All,
+            Some(m) => Err(anyhow!("{} Wrong syntax: forwarded_headers = {}", path, m))?,
+        };
+        let trusted_proxies = ip::parse_nets(&trusted_proxies.unwrap_or_default(), path)?;
+        Ok(Some(Self {
+            mode,
+            trusted_proxies,
+        }))
+    }
+
+    pub fn x_forwarded(&self) -> bool {
+        self.mode != ForwardedMode::Forwarded
+    }
+
+    pub fn forwarded(&self) -> bool {
+        self.mode != ForwardedMode::XForwarded
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::ValueMatch;
+use anyhow::anyhow;
+use http::{HeaderName, HeaderValue, StatusCode};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeaderMode {
+    /// replace any existing header
+    Set,
+    Append,
+    /// only when the header is absent
+    Default,
+}
+
+#[derive(Clone, Debug)]
+pub struct HeaderRule {
+    pub name: String,
+    pub value: String,
+    pub mode: HeaderMode,
+    /// inclusive ranges of status, like `2xx` or `404`
+    pub status: Option<Vec<(u16, u16)>>,
+    pub content_type: Option<ValueMatch>,
+    /// expand request variables in `value`
+    pub template: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum HeaderRuleRaw {
+    Text(String),
+    Table {
+        value: String,
+        mode: Option<String>,
+        status: Option<Vec<String>>,
+        content_type: Option<String>,
+        template: Option<bool>,
+    },
+}
+
+fn parse_status(status: &str, path: &str) -> anyhow::Result<(u16, u16)> {
+    let err = || anyhow!("{} Wrong syntax: status = {}", path, status);
+    match status.to_lowercase().strip_suffix("xx") {
+        Some(class @ ("1" | "2" | "3" | "4" | "5")) => {
+            let class = class.parse::<u16>().unwrap();
+            Ok((class * 100, class * 100 + 99))
+        }
+        Some(_) => Err(err()),
+        None => {
+            let code = status.parse::<u16>().map_err(|_| err())?;
+            StatusCode::from_u16(code).map_err(|_| err())?;
+            Ok((code, code))
+        }
+    }
+}
+
+impl HeaderRule {
+    /// Rules of `headers_request` or `headers_response` in the written order, `status` only
+    /// works in responses.
+    pub fn from_map(
+        map: IndexMap<String, HeaderRuleRaw>,
+        response: bool,
+        path: &str,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut result = Vec::new();
+        for (name, raw) in map {
+            HeaderName::from_str(&name)
+                .map_err(|_| anyhow!("{} Wrong syntax: header {}", path, name))?;
+            result.push(match raw {
+                HeaderRuleRaw::Text(value) => HeaderRule {
+                    name,
+                    value,
+                    mode: HeaderMode::Set,
+                    status: None,
+                    content_type: None,
+                    template: false,
+                },
+                HeaderRuleRaw::Table {
+                    value,
+                    mode,
+                    status,
+                    content_type,
+                    template,
+                } => {
+                    let mode = match mode.as_deref() {
+                        None | Some("set") => HeaderMode::Set,
+                        Some("append") => HeaderMode::Append,
+                        Some("default") => HeaderMode::Default,
+                        Some(m) => Err(anyhow!(
+                            "{} Wrong syntax: header {} mode = {}",
+                            path,
+                            name,
+                            m
+                        ))?,
+                    };
+                    let status = match status {
+                        None => None,
+                        Some(_) if !response => Err(anyhow!(
+                            "{} Wrong syntax: header {}, status only works in headers_response",
+                            path,
+                            name
+                        ))?,
+                        Some(list) => Some(
+                            list.iter()
+                                .map(|s| parse_status(s, path))
+                                .collect::<anyhow::Result<Vec<(u16, u16)>>>()?,
+                        ),
+                    };
+                    let content_type = match content_type {
+                        None => None,
+                        Some(c) => Some(ValueMatch::new(&c, path)?),
+                    };
+                    HeaderRule {
+                        name,
+                        value,
+                        mode,
+                        status,
+                        content_type,
+                        template: template.unwrap_or(false),
+                    }
+                }
+            });
+        }
+        Ok(result)
+    }
+
+    /// Check `status` and `content_type` against the message, the parameters of content type
+    /// like `charset` are ignored.
+    pub fn is_match(&self, status: Option<StatusCode>, content_type: Option<&HeaderValue>) -> bool {
+        if let Some(ranges) = &self.status {
+            match status {
+                Some(status)
+                    if ranges
+                        .iter()
+                        .any(|(lo, hi)| (*lo..=*hi).contains(&status.as_u16())) => {}
+                _ => return false,
+            }
+        }
+        if let Some(value) = &self.content_type {
+            let content_type = content_type
+                .and_then(|c| c.to_str().ok())
+                .map(|c| c.split(';').next().unwrap_or_default().trim());
+            match content_type {
+                Some(c) if value.is_match(c) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+pub fn parse_header_names(names: Vec<String>, path: &str) -> anyhow::Result<Vec<String>> {
+    for name in &names {
+        HeaderName::from_str(name)
+            .map_err(|_| anyhow!("{} Wrong syntax: header {}", path, name))?;
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderRule, HeaderRuleRaw};
+    use indexmap::IndexMap;
+
+    #[test]
+    fn rules_keep_written_order() {
+        let map: IndexMap<String, HeaderRuleRaw> = toml::from_str(
+            r#"
+            Vary = { value = "Origin", mode = "append" }
+            X-B = "b"
+            Cache-Control = { value = "no-store", mode = "default" }
+            X-A = "a"
+            "#,
+        )
+        .unwrap();
+        let rules = HeaderRule::from_map(map, true, "test.toml").unwrap();
+        let names = rules.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Vary", "X-B", "Cache-Control", "X-A"]);
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::anyhow;
+use http::StatusCode;
+use serde::Deserialize;
+
+#[derive(Clone, Debug)]
+pub struct RedirectHttps {
+    pub port: u16,
+    pub status: StatusCode,
+}
+
+#[derive(Deserialize)]
+pub struct RedirectHttpsRaw {
+    pub port: Option<u16>,
+    pub status: Option<u16>,
+}
+
+impl RedirectHttps {
+    pub fn from_raw(raw: RedirectHttpsRaw, path: &str) -> anyhow::Result<Self> {
+        let status = raw.status.unwrap_or(301);
+        if !matches!(status, 301 | 302 | 307 | 308) {
+            Err(anyhow!(
+                "{} Wrong syntax: redirect_https.status = {}, expect 301, 302, 307 or 308",
+                path,
+                status
+            ))?;
+        }
+        Ok(Self {
+            port: raw.port.unwrap_or(443),
+            status: StatusCode::from_u16(status)?,
+        })
+    }
+
+    /// Build `https://host[:port]/path?query` from the `Host` header and the raw request uri.
+    pub fn location(&self, host: &str, uri: &str) -> String {
+        let host = match host.rsplit_once(':') {
+            // keep ipv6 literal like `[::1]` intact
+            Some((h, port)) if !port.contains(']') => h,
+            _ => host,
+        };
+        if self.port == 443 {
+            format!("https://{}{}", host, uri)
+        } else {
+            format!("https://{}:{}{}", host, self.port, uri)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Hsts {
+    pub max_age: u64,
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+#[derive(Deserialize)]
+pub struct HstsRaw {
+    pub max_age: Option<u64>,
+    pub include_subdomains: Option<bool>,
+    pub preload: Option<bool>,
+}
+
+impl Hsts {
+    pub fn from_raw(raw: HstsRaw) -> Self {
+        Self {
+            max_age: raw.max_age.unwrap_or(31536000),
+            include_subdomains: raw.include_subdomains.unwrap_or_default(),
+            preload: raw.preload.unwrap_or_default(),
+        }
+    }
+
+    /// Value of `Strict-Transport-Security`.
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::{parse_header_names, ValueMatch};
+use crate::util::path;
+use anyhow::anyhow;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+
+#[derive(Clone)]
+pub struct JwtKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: DecodingKey,
+}
+
+impl Debug for JwtKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+/// Validate bearer tokens before answering the request.
+#[derive(Clone, Debug)]
+pub struct Jwt {
+    pub keys: Vec<JwtKey>,
+    /// cookie carrying the token, when `Authorization` is absent
+    pub token_cookie: Option<String>,
+    /// query parameter carrying the token, when neither of above is present
+    pub token_query: Option<String>,
+    pub issuer: Option<Vec<String>>,
+    pub audience: Option<Vec<String>>,
+    /// seconds of clock skew allowed for `exp` and `nbf`
+    pub leeway: u64,
+    pub claims: Vec<(String, ValueMatch)>,
+    /// header and the claim sent in it to upstream service
+    pub claims_headers: Vec<(String, String)>,
+}
+
+#[derive(Deserialize)]
+pub struct JwtRaw {
+    pub algorithm: Option<String>,
+    pub key_file: Option<String>,
+    pub jwks_file: Option<String>,
+    pub token_cookie: Option<String>,
+    pub token_query: Option<String>,
+    pub issuer: Option<Vec<String>>,
+    pub audience: Option<Vec<String>>,
+    pub leeway: Option<u64>,
+    pub claims: Option<HashMap<String, String>>,
+    pub claims_headers: Option<HashMap<String, String>>,
+}
+
+fn parse_algorithm(algorithm: &str, path: &str) -> anyhow::Result<Algorithm> {
+    match algorithm {
+        "HS256" => Ok(Algorithm::HS256),
+        "RS256" => Ok(Algorithm::RS256),
+        "ES256" => Ok(Algorithm::ES256),
+        _ => Err(anyhow!(
+            "{} Wrong syntax: jwt.algorithm = {}, should be HS256, RS256 or ES256",
+            path,
+            algorithm
+        )),
+    }
+}
+
+fn key_from_file(algorithm: Algorithm, file: &str, path: &str) -> anyhow::Result<JwtKey> {
+    let content = fs::read(file).map_err(|e| anyhow!("{} Failed to read {}: {}", path, file, e))?;
+    let key = match algorithm {
+        Algorithm::HS256 => Ok(DecodingKey::from_secret(content.trim_ascii_end())),
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(&content),
+        _ => DecodingKey::from_ec_pem(&content),
+    }
+    .map_err(|e| anyhow!("{} Wrong syntax: key {}, {}", path, file, e))?;
+    Ok(JwtKey {
+        kid: None,
+        algorithm,
+        key,
+    })
+}
+
+fn jwk_algorithm(jwk: &Jwk) -> Option<Algorithm> {
+    match (&jwk.common.key_algorithm, &jwk.algorithm) {
+        (Some(KeyAlgorithm::HS256), AlgorithmParameters::OctetKey(_))
+        | (None, AlgorithmParameters::OctetKey(_)) => Some(Algorithm::HS256),
+        (Some(KeyAlgorithm::RS256), AlgorithmParameters::RSA(_))
+        | (None, AlgorithmParameters::RSA(_)) => Some(Algorithm::RS256),
+        (Some(KeyAlgorithm::ES256), AlgorithmParameters::EllipticCurve(ec))
+        | (None, AlgorithmParameters::EllipticCurve(ec))
+            if ec.curve == EllipticCurve::P256 =>
+        {
+            Some(Algorithm::ES256)
+        }
+        _ => None,
+    }
+}
+
+fn keys_from_jwks(file: &str, path: &str) -> anyhow::Result<Vec<JwtKey>> {
+    let content =
+        fs::read_to_string(file).map_err(|e| anyhow!("{} Failed to read {}: {}", path, file, e))?;
+    let set: JwkSet = serde_json::from_str(&content)
+        .map_err(|e| anyhow!("{} Wrong syntax: jwks {}, {}", path, file, e))?;
+    let mut keys = Vec::new();
+    for jwk in &set.keys {
+        let algorithm = match jwk_algorithm(jwk) {
+            Some(algorithm) => algorithm,
+            // keys of other algorithms are not accepted
+            None => continue,
+        };
+        let key = DecodingKey::from_jwk(jwk)
+            .map_err(|e| anyhow!("{} Wrong syntax: jwks {}, {}", path, file, e))?;
+        keys.push(JwtKey {
+            kid: jwk.common.key_id.clone(),
+            algorithm,
+            key,
+        });
+    }
+    if keys.is_empty() {
+        Err(anyhow!(
+            "{} Wrong syntax: jwks {} has no HS256, RS256 or ES256 key",
+            path,
+            file
+        ))?;
+    }
+    Ok(keys)
+}
+
+impl Jwt {
+    pub fn from_raw(raw: JwtRaw, path: &str) -> anyhow::Result<Self> {
+        let keys = match (raw.key_file, raw.jwks_file) {
+            (Some(file), None) => {
+                let algorithm = match raw.algorithm {
+                    Some(algorithm) => parse_algorithm(&algorithm, path)?,
+                    None => Err(anyhow!(
+                        "{} Wrong syntax: jwt.key_file requires algorithm",
+                        path
+                    ))?,
+                };
+                vec![key_from_file(algorithm, &path::resolve(path, &file), path)?]
+            }
+            (None, Some(file)) => {
+                if raw.algorithm.is_some() {
+                    Err(anyhow!(
+                        "{} Wrong syntax: jwt.algorithm only works with key_file",
+                        path
+                    ))?;
+                }
+                keys_from_jwks(&path::resolve(path, &file), path)?
+            }
+            _ => Err(anyhow!(
+                "{} Wrong syntax: jwt requires one of key_file and jwks_file",
+                path
+            ))?,
+        };
+        let mut claims = Vec::new();
+        for (claim, value) in raw.claims.unwrap_or_default() {
+            claims.push((claim, ValueMatch::new(&value, path)?));
+        }
+        let claims_headers = raw.claims_headers.unwrap_or_default();
+        parse_header_names(claims_headers.keys().cloned().collect(), path)?;
+        Ok(Self {
+            keys,
+            token_cookie: raw.token_cookie,
+            token_query: raw.token_query,
+            issuer: raw.issuer,
+            audience: raw.audience,
+            leeway: raw.leeway.unwrap_or(60),
+            claims,
+            claims_headers: claims_headers.into_iter().collect(),
+        })
+    }
+}
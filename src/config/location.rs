@@ -1,5 +1,7 @@
+use crate::config::tokenize;
 use anyhow::anyhow;
 use regex::Regex;
+use serde::Deserialize;
 
 #[derive(Clone)]
 #[derive(Debug)]
@@ -9,30 +11,123 @@ pub enum Location {
     Regex(Regex),
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum LocationRaw {
+    Text(String),
+    Table {
+        #[serde(rename = "type")]
+        location_type: Option<String>,
+        uri: String,
+    },
+}
+
 impl Location {
     pub fn new(location: String, path: &str) -> anyhow::Result<Self> {
-        if location.starts_with('/') {
-            Ok(Location::Start(location))
-        } else {
-            let parts = location.split(' ').collect::<Vec<&str>>();
-            if parts.len() != 1 {
-                Err(anyhow!("{} Wrong syntax: location = {}", path, location))?;
+        let parts = tokenize(&location)
+            .map_err(|err| anyhow!("{} Wrong syntax: location = {}, {}", path, location, err))?;
+        match parts.len() {
+            1 if parts[0].starts_with('/') => Ok(Location::Start(parts[0].clone())),
+            2 => Location::with_type(&parts[0], &parts[1], path)
+                .map_err(|_| anyhow!("{} Wrong syntax: location = {}", path, location)),
+            _ => Err(anyhow!("{} Wrong syntax: location = {}", path, location))?,
+        }
+    }
+
+    pub fn from_raw(raw: LocationRaw, path: &str) -> anyhow::Result<Self> {
+        match raw {
+            LocationRaw::Text(location) => Location::new(location, path),
+            LocationRaw::Table { location_type, uri } => {
+                Location::with_type(location_type.as_deref().unwrap_or("^"), &uri, path)
             }
-            if parts[0] == "^" {
-                Ok(Location::Start(if parts[1].ends_with('/') {
-                    String::from(parts[1])
-                } else {
-                    format!("{}{}", parts[1], '/')
-                }))
-            } else if parts[0] == "=" {
-                Ok(Location::Equal(String::from(parts[1])))
-            } else if parts[0] == "~" {
-                Ok(Location::Regex(
-                    Regex::new(parts[1]).map_err(|err| anyhow!("{} {}", path, err.to_string()))?,
-                ))
+        }
+    }
+
+    fn with_type(location_type: &str, uri: &str, path: &str) -> anyhow::Result<Self> {
+        match location_type {
+            "^" => Ok(Location::Start(if uri.ends_with('/') {
+                String::from(uri)
             } else {
-                Err(anyhow!("{} Wrong syntax: location = {}", path, location))?
-            }
+                format!("{}{}", uri, '/')
+            })),
+            "=" => Ok(Location::Equal(String::from(uri))),
+            "~" => Ok(Location::Regex(
+                Regex::new(uri).map_err(|err| anyhow!("{} {}", path, err.to_string()))?,
+            )),
+            _ => Err(anyhow!(
+                "{} Wrong syntax: location type = {}",
+                path,
+                location_type
+            ))?,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Location, LocationRaw};
+    use crate::util::url::decode_path;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Config {
+        location: Vec<LocationRaw>,
+    }
+
+    fn parse(location: &str) -> anyhow::Result<Location> {
+        Location::new(String::from(location), "test.toml")
+    }
+
+    #[test]
+    fn string_syntax() {
+        assert!(matches!(parse("/public").unwrap(), Location::Start(l) if l == "/public"));
+        assert!(matches!(parse("^ /public").unwrap(), Location::Start(l) if l == "/public/"));
+        assert!(matches!(parse("= /exact").unwrap(), Location::Equal(l) if l == "/exact"));
+        assert!(matches!(parse("= \"/a b\"").unwrap(), Location::Equal(l) if l == "/a b"));
+        let re = parse(r#"~ "^/static/.* (gif|jpg)$""#).unwrap();
+        assert!(matches!(re, Location::Regex(re) if re.is_match("/static/a b gif")));
+        let re = parse(r"~ ^/(\d+)$").unwrap();
+        assert!(matches!(re, Location::Regex(re) if re.is_match("/42")));
+    }
+
+    #[test]
+    fn wrong_syntax() {
+        assert!(parse("public").is_err());
+        assert!(parse("= /a /b").is_err());
+        assert!(parse("! /a").is_err());
+        assert!(parse("~ (").is_err());
+        assert!(parse("= \"/a").is_err());
+    }
+
+    #[test]
+    fn table_syntax() {
+        let config: Config = toml::from_str(
+            r#"
+            location = [
+                "= /exact",
+                { type = "~", uri = "^/a b/(.*)$" },
+                { uri = "/start" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let location = config
+            .location
+            .into_iter()
+            .map(|l| Location::from_raw(l, "test.toml"))
+            .collect::<anyhow::Result<Vec<Location>>>()
+            .unwrap();
+        assert!(matches!(&location[0], Location::Equal(l) if l == "/exact"));
+        assert!(matches!(&location[1], Location::Regex(re) if re.is_match("/a b/c")));
+        assert!(matches!(&location[2], Location::Start(l) if l == "/start/"));
+    }
+
+    #[test]
+    fn matches_decoded_request() {
+        let uri = decode_path(&"/files/a%20b%20(copy)?name=a%20b".parse().unwrap());
+        let re = parse(r"~ '^/files/.* \(copy\)'").unwrap();
+        assert!(matches!(re, Location::Regex(re) if re.is_match(&uri)));
+        let uri = decode_path(&"/a%20b".parse().unwrap());
+        assert!(matches!(parse("= \"/a b\"").unwrap(), Location::Equal(l) if l == uri));
+    }
+}
@@ -5,6 +5,28 @@ mod source;
 mod static_server;
 mod location;
 mod rewrite;
+mod upstream_tls;
+mod https;
+mod return_source;
+mod tokenizer;
+mod condition;
+mod sni;
+mod forwarded;
+mod headers;
+mod error_page;
+mod client_ip;
+mod access;
+mod auth_basic;
+mod forward_auth;
+mod jwt;
+mod secure_link;
+mod rate_limit;
+mod concurrency;
+mod request_limits;
+mod client_timeouts;
+mod cors;
+mod security_headers;
+mod waf;
 
 pub use config::*;
 pub use import_able::*;
@@ -13,3 +35,25 @@ pub use source::*;
 pub use static_server::*;
 pub use location::*;
 pub use rewrite::*;
+pub use upstream_tls::*;
+pub use https::*;
+pub use return_source::*;
+pub use tokenizer::*;
+pub use condition::*;
+pub use sni::*;
+pub use forwarded::*;
+pub use headers::*;
+pub use error_page::*;
+pub use client_ip::*;
+pub use access::*;
+pub use auth_basic::*;
+pub use forward_auth::*;
+pub use jwt::*;
+pub use secure_link::*;
+pub use rate_limit::*;
+pub use concurrency::*;
+pub use request_limits::*;
+pub use client_timeouts::*;
+pub use cors::*;
+pub use security_headers::*;
+pub use waf::*;
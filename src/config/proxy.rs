@@ -1,4 +1,12 @@
-use crate::config::{Importable, Location, Rewrite, Source, SourceRaw};
+use crate::config::{
+    parse_header_names, ClientTimeouts, Concurrency, ConcurrencyRaw, ErrorPageRaw, ErrorPages,
+    ForwardedHeaders, HeaderRule, HeaderRuleRaw, Hsts, HstsRaw, Importable, ProxyProtocol,
+    ProxyProtocolVersion, RealIp, RedirectHttps, RedirectHttpsRaw, RequestLimits, RequestLimitsRaw,
+    SecurityHeaders, SecurityHeadersRaw, Source, SourceOptions, SourceOptionsRaw, SourceRaw,
+    UpstreamTls, UpstreamTlsRaw, Waf, WafRaw,
+};
+use anyhow::anyhow;
+use indexmap::IndexMap;
 use pingora::lb::health_check;
 use pingora::prelude::{background_service, LoadBalancer, RoundRobin};
 use serde::Deserialize;
@@ -13,13 +21,15 @@ pub struct Proxy {
     pub host: Option<String>,
     pub port: u16,
     pub ssl: bool,
+    pub upstream_tls: Option<UpstreamTls>,
+    pub forwarded_headers: Option<ForwardedHeaders>,
+    pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+    pub concurrency: Option<Concurrency>,
+    pub request_limits: Option<RequestLimits>,
     pub load_balancer: Option<Arc<LoadBalancer<RoundRobin>>>,
-    pub sni: Option<String>,
-    pub location: Vec<Location>,
-    pub rewrite: Option<Vec<Rewrite>>,
-    pub fallback: Vec<String>,
-    pub headers_request: Option<HashMap<String, String>>,
-    pub headers_response: Option<HashMap<String, String>>,
+    pub headers_request: Option<Vec<HeaderRule>>,
+    pub headers_request_remove: Vec<String>,
+    pub options: SourceOptions,
 }
 
 impl Debug for Proxy {
@@ -29,13 +39,15 @@ impl Debug for Proxy {
             .field("host", &self.host)
             .field("port", &self.port)
             .field("ssl", &self.ssl)
+            .field("upstream_tls", &self.upstream_tls)
+            .field("forwarded_headers", &self.forwarded_headers)
+            .field("send_proxy_protocol", &self.send_proxy_protocol)
+            .field("concurrency", &self.concurrency)
+            .field("request_limits", &self.request_limits)
             .field("load_balancer", &self.load_balancer.is_some())
-            .field("sni", &self.sni)
-            .field("location", &self.location)
-            .field("rewrite", &self.rewrite)
-            .field("fallback", &self.fallback)
             .field("headers_request", &self.headers_request)
-            .field("headers_response", &self.headers_response)
+            .field("headers_request_remove", &self.headers_request_remove)
+            .field("options", &self.options)
             .finish()
     }
 }
@@ -47,12 +59,16 @@ pub struct ProxyRaw {
     pub host: Option<String>,
     pub port: u16,
     pub ssl: bool,
-    pub sni: Option<String>,
-    pub location: Option<Vec<String>>,
-    pub rewrite: Option<Vec<String>>,
-    pub fallback: Option<Vec<String>>,
-    pub headers_request: Option<Importable<HashMap<String, String>>>,
-    pub headers_response: Option<Importable<HashMap<String, String>>>,
+    pub upstream_tls: Option<UpstreamTlsRaw>,
+    pub forwarded_headers: Option<String>,
+    pub trusted_proxies: Option<Vec<String>>,
+    pub send_proxy_protocol: Option<String>,
+    pub concurrency: Option<ConcurrencyRaw>,
+    pub request_limits: Option<RequestLimitsRaw>,
+    pub headers_request: Option<Importable<IndexMap<String, HeaderRuleRaw>>>,
+    pub headers_request_remove: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub options: SourceOptionsRaw,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -68,20 +84,52 @@ pub struct Server {
     pub threads: Option<usize>,
     pub check_status: bool,
     // pub check_duration: u64,
+    pub redirect_https: Option<RedirectHttps>,
+    pub hsts: Option<Hsts>,
+    pub error_page: ErrorPages,
+    pub proxy_protocol: ProxyProtocol,
+    pub real_ip: Option<RealIp>,
+    pub concurrency: Option<Concurrency>,
+    pub timeouts: ClientTimeouts,
+    pub security_headers: Option<SecurityHeaders>,
+    pub waf: Option<Waf>,
 }
 
 #[derive(Deserialize)]
 pub struct ServerRaw {
-    pub source: Importable<HashMap<String, Importable<SourceRaw>>>,
+    pub source: Option<Importable<HashMap<String, Importable<SourceRaw>>>>,
     pub ssl: Option<Ssl>,
     pub threads: Option<usize>,
     pub check_status: Option<bool>,
     pub check_duration: Option<u64>,
+    pub redirect_https: Option<RedirectHttpsRaw>,
+    pub hsts: Option<HstsRaw>,
+    pub error_page: Option<HashMap<String, ErrorPageRaw>>,
+    pub proxy_protocol: Option<String>,
+    pub proxy_protocol_from: Option<Vec<String>>,
+    pub real_ip_header: Option<String>,
+    pub real_ip_from: Option<Vec<String>>,
+    pub concurrency: Option<ConcurrencyRaw>,
+    pub client_header_timeout: Option<u64>,
+    pub client_body_timeout: Option<u64>,
+    pub keepalive_timeout: Option<u64>,
+    pub keepalive_requests: Option<u32>,
+    pub send_timeout: Option<u64>,
+    pub security_headers: Option<SecurityHeadersRaw>,
+    pub waf: Option<WafRaw>,
 }
 
 impl Server {
     pub fn from_raw(raw: ServerRaw, path: &str) -> anyhow::Result<Self> {
-        let (source_raw, source_path) = raw.source.import(path)?;
+        let (source_raw, source_path) = match raw.source {
+            Some(source) => source.import(path)?,
+            None => {
+                if raw.redirect_https.is_none() {
+                    Err(anyhow!("{} Wrong syntax: source is required", path))?;
+                }
+                (HashMap::new(), String::from(path))
+            }
+        };
         let mut source = HashMap::new();
         let check_status = raw.check_status.unwrap_or_default();
         let check_duration = raw.check_duration.unwrap_or(1000);
@@ -92,12 +140,58 @@ impl Server {
                 Source::from_raw(sr.0, &sr.1, check_status, check_duration)?,
             );
         }
+        let redirect_https = match raw.redirect_https {
+            Some(r) => Some(RedirectHttps::from_raw(r, path)?),
+            None => None,
+        };
+        let hsts = match raw.hsts {
+            Some(h) => {
+                if raw.ssl.is_none() {
+                    Err(anyhow!("{} Wrong syntax: hsts requires ssl", path))?;
+                }
+                Some(Hsts::from_raw(h))
+            }
+            None => None,
+        };
+        let error_page = ErrorPages::from_raw(raw.error_page.unwrap_or_default(), path)?;
+        let proxy_protocol =
+            ProxyProtocol::from_raw(raw.proxy_protocol, raw.proxy_protocol_from, path)?;
+        let real_ip = RealIp::from_raw(raw.real_ip_header, raw.real_ip_from, path)?;
+        let concurrency = match raw.concurrency {
+            Some(c) => Some(Concurrency::from_raw(c, path)?),
+            None => None,
+        };
+        let timeouts = ClientTimeouts::from_raw(
+            raw.client_header_timeout,
+            raw.client_body_timeout,
+            raw.keepalive_timeout,
+            raw.keepalive_requests,
+            raw.send_timeout,
+            path,
+        )?;
+        let security_headers = match raw.security_headers {
+            Some(h) => Some(SecurityHeaders::from_raw(h, path)?),
+            None => None,
+        };
+        let waf = match raw.waf {
+            Some(waf) => Some(Waf::from_raw(waf, path)?),
+            None => None,
+        };
         Ok(Self {
             source,
             ssl: raw.ssl,
             threads: raw.threads,
             check_status,
             // check_duration,
+            redirect_https,
+            hsts,
+            error_page,
+            proxy_protocol,
+            real_ip,
+            concurrency,
+            timeouts,
+            security_headers,
+            waf,
         })
     }
 }
@@ -119,48 +213,42 @@ impl Proxy {
         } else {
             None
         };
-        let sni = raw.sni.map(|s| s.to_lowercase());
-        let location = match raw.location {
-            None => vec![Location::Start(String::from("/"))],
-            Some(loc) => {
-                let mut result: Vec<Location> = Vec::new();
-                for location in loc {
-                    result.push(Location::new(location, path)?)
-                }
-                result
-            }
+        let headers_request = match raw.headers_request {
+            Some(h) => Some(HeaderRule::from_map(h.import(path)?.0, false, path)?),
+            None => None,
         };
-        let rewrite = match raw.rewrite {
+        let headers_request_remove =
+            parse_header_names(raw.headers_request_remove.unwrap_or_default(), path)?;
+        let upstream_tls = match raw.upstream_tls {
+            Some(tls) => Some(UpstreamTls::from_raw(tls, path)?),
             None => None,
-            Some(list) => Some({
-                let mut vec: Vec<Rewrite> = Vec::new();
-                list.iter().try_for_each(|v| -> anyhow::Result<()> {
-                    vec.push(Rewrite::new(v.clone(), path)?);
-                    Ok(())
-                })?;
-                vec
-            }),
         };
-        let headers_request = match raw.headers_request {
-            Some(h) => Some(h.import(path)?.0),
+        let forwarded_headers =
+            ForwardedHeaders::from_raw(raw.forwarded_headers, raw.trusted_proxies, path)?;
+        let send_proxy_protocol = ProxyProtocolVersion::from_raw(raw.send_proxy_protocol, path)?;
+        let concurrency = match raw.concurrency {
+            Some(c) => Some(Concurrency::from_raw(c, path)?),
             None => None,
         };
-        let headers_response = match raw.headers_response {
-            Some(h) => Some(h.import(path)?.0),
+        let request_limits = match raw.request_limits {
+            Some(l) => Some(RequestLimits::from_raw(l, path)?),
             None => None,
         };
+        let options = SourceOptions::from_raw(raw.options, path)?;
         Ok(Self {
             ip: raw.ip,
             host: raw.host,
             port: raw.port,
             ssl: raw.ssl,
+            upstream_tls,
+            forwarded_headers,
+            send_proxy_protocol,
+            concurrency,
+            request_limits,
             load_balancer,
-            sni,
-            location,
-            rewrite,
-            fallback: raw.fallback.unwrap_or_default(),
             headers_request,
-            headers_response,
+            headers_request_remove,
+            options,
         })
     }
 }
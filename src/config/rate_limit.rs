@@ -0,0 +1,91 @@
+use crate::util::rate_limit::Buckets;
+use anyhow::anyhow;
+use http::HeaderName;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub enum RateLimitKey {
+    ClientIp,
+    Header(HeaderName),
+    Claim(String),
+    /// one bucket for the whole source
+    Route,
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    pub key: RateLimitKey,
+    /// tokens per second
+    pub rate: f64,
+    pub burst: u32,
+    /// only log the requests over the limit
+    pub dry_run: bool,
+    pub buckets: Arc<Buckets>,
+}
+
+#[derive(Deserialize)]
+pub struct RateLimitRaw {
+    pub key: Option<String>,
+    pub rate: String,
+    pub burst: Option<u32>,
+    pub dry_run: Option<bool>,
+}
+
+impl RateLimitKey {
+    /// `client_ip`, `header NAME`, `claim NAME` or `route`.
+    fn new(key: &str, path: &str) -> anyhow::Result<Self> {
+        match key.split_once(' ') {
+            None if key == "client_ip" => Ok(RateLimitKey::ClientIp),
+            None if key == "route" => Ok(RateLimitKey::Route),
+            Some(("header", name)) => Ok(RateLimitKey::Header(
+                HeaderName::from_str(name.trim())
+                    .map_err(|_| anyhow!("{} Wrong syntax: rate_limit.key = {}", path, key))?,
+            )),
+            Some(("claim", name)) => Ok(RateLimitKey::Claim(String::from(name.trim()))),
+            _ => Err(anyhow!("{} Wrong syntax: rate_limit.key = {}", path, key)),
+        }
+    }
+}
+
+/// `10/s`, `100/m` or `1000/h`.
+fn parse_rate(rate: &str, path: &str) -> anyhow::Result<f64> {
+    let (count, period) =
+        rate.split_once('/')
+            .ok_or(anyhow!("{} Wrong syntax: rate_limit.rate = {}", path, rate))?;
+    let count = count
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| anyhow!("{} Wrong syntax: rate_limit.rate = {}", path, rate))?;
+    let seconds = match period.trim() {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => Err(anyhow!("{} Wrong syntax: rate_limit.rate = {}", path, rate))?,
+    };
+    if !count.is_finite() || count <= 0.0 {
+        Err(anyhow!("{} Wrong syntax: rate_limit.rate = {}", path, rate))?;
+    }
+    Ok(count / seconds)
+}
+
+impl RateLimit {
+    pub fn from_raw(raw: RateLimitRaw, path: &str) -> anyhow::Result<Self> {
+        let rate = parse_rate(&raw.rate, path)?;
+        let burst = raw.burst.unwrap_or(rate.ceil() as u32).max(1);
+        Ok(Self {
+            key: RateLimitKey::new(raw.key.as_deref().unwrap_or("client_ip"), path)?,
+            rate,
+            burst,
+            dry_run: raw.dry_run.unwrap_or_default(),
+            buckets: Arc::new(Buckets::default()),
+        })
+    }
+
+    pub fn from_list(list: Vec<RateLimitRaw>, path: &str) -> anyhow::Result<Vec<Self>> {
+        list.into_iter()
+            .map(|raw| RateLimit::from_raw(raw, path))
+            .collect()
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::anyhow;
+use pingora::http::RequestHeader;
+use serde::Deserialize;
+
+/// Limits on the size of requests to a proxy source.
+#[derive(Clone, Debug)]
+pub struct RequestLimits {
+    /// bytes of the request body, 413 when exceeded
+    pub max_body_size: Option<usize>,
+    /// number of request headers, 431 when exceeded
+    pub max_headers: Option<usize>,
+    /// bytes of the request line and headers, 431 when exceeded
+    pub max_header_size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum SizeRaw {
+    Bytes(usize),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+pub struct RequestLimitsRaw {
+    pub max_body_size: Option<SizeRaw>,
+    pub max_headers: Option<usize>,
+    pub max_header_size: Option<SizeRaw>,
+}
+
+impl SizeRaw {
+    /// Bytes, or a number with the suffix `k`, `m` or `g`.
+    pub fn parse(self, name: &str, path: &str) -> anyhow::Result<usize> {
+        let text = match self {
+            SizeRaw::Bytes(bytes) => return Ok(bytes),
+            SizeRaw::Text(text) => text,
+        };
+        let lower = text.trim().to_ascii_lowercase();
+        let (number, unit) = match lower.char_indices().last() {
+            Some((i, 'k')) => (&lower[..i], 1 << 10),
+            Some((i, 'm')) => (&lower[..i], 1 << 20),
+            Some((i, 'g')) => (&lower[..i], 1 << 30),
+            _ => (lower.as_str(), 1),
+        };
+        number
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_mul(unit))
+            .ok_or(anyhow!(
+                "{} Wrong syntax: {} = {}, should be bytes like 1024, 16k or 10m",
+                path,
+                name,
+                text
+            ))
+    }
+}
+
+impl RequestLimits {
+    pub fn from_raw(raw: RequestLimitsRaw, path: &str) -> anyhow::Result<Self> {
+        let max_body_size = match raw.max_body_size {
+            Some(size) => Some(size.parse("request_limits.max_body_size", path)?),
+            None => None,
+        };
+        let max_header_size = match raw.max_header_size {
+            Some(size) => Some(size.parse("request_limits.max_header_size", path)?),
+            None => None,
+        };
+        if raw.max_headers == Some(0) || max_header_size == Some(0) {
+            Err(anyhow!(
+                "{} Wrong syntax: request_limits of headers should be positive",
+                path
+            ))?;
+        }
+        Ok(Self {
+            max_body_size,
+            max_headers: raw.max_headers,
+            max_header_size,
+        })
+    }
+
+    /// Whether the header count or size of the request is over the limits.
+    pub fn headers_exceeded(&self, req: &RequestHeader) -> bool {
+        if self.max_headers.is_some_and(|max| req.headers.len() > max) {
+            return true;
+        }
+        match self.max_header_size {
+            Some(max) => {
+                // request line, then `name: value` of each header, with CRLF
+                let size = req.method.as_str().len()
+                    + req.uri.to_string().len()
+                    + 12
+                    + req
+                        .headers
+                        .iter()
+                        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+                        .sum::<usize>();
+                size > max
+            }
+            None => false,
+        }
+    }
+
+    pub fn body_exceeded(&self, length: usize) -> bool {
+        self.max_body_size.is_some_and(|max| length > max)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::config::{SourceOptions, SourceOptionsRaw};
+use crate::util::path;
+use anyhow::anyhow;
+use http::StatusCode;
+use serde::Deserialize;
+use std::fs;
+
+#[derive(Clone, Debug)]
+pub struct Return {
+    pub status: StatusCode,
+    pub redirect: Option<String>,
+    pub body: Option<String>,
+    pub content_type: String,
+    pub options: SourceOptions,
+}
+
+#[derive(Deserialize)]
+pub struct ReturnRaw {
+    pub source_type: Option<String>,
+    pub status: u16,
+    pub redirect: Option<String>,
+    pub body: Option<String>,
+    pub body_file: Option<String>,
+    pub content_type: Option<String>,
+    #[serde(flatten)]
+    pub options: SourceOptionsRaw,
+}
+
+impl Return {
+    pub fn from_raw(raw: ReturnRaw, path: &str) -> Result<Self, anyhow::Error> {
+        let status = StatusCode::from_u16(raw.status)
+            .map_err(|_| anyhow!("{} Wrong syntax: status = {}", path, raw.status))?;
+        if raw.redirect.is_some() && !status.is_redirection() {
+            Err(anyhow!(
+                "{} Wrong syntax: status = {}, redirect requires a 3xx status",
+                path,
+                raw.status
+            ))?;
+        }
+        let body = match (raw.body, raw.body_file) {
+            (Some(_), Some(_)) => Err(anyhow!(
+                "{} Wrong syntax: body and body_file cannot be set together",
+                path
+            ))?,
+            (Some(body), None) => Some(body),
+            (None, Some(file)) => {
+                let file = path::resolve(path, &file);
+                Some(fs::read_to_string(&file).or(Err(anyhow!("Cannot read file {}", &file)))?)
+            }
+            (None, None) => None,
+        };
+        let options = SourceOptions::from_raw(raw.options, path)?;
+        Ok(Self {
+            status,
+            redirect: raw.redirect,
+            body,
+            content_type: raw
+                .content_type
+                .unwrap_or(String::from("text/plain; charset=utf-8")),
+            options,
+        })
+    }
+}
@@ -1,25 +1,77 @@
+use crate::config::tokenize;
 use anyhow::anyhow;
+use http::StatusCode;
 use regex::Regex;
+use serde::Deserialize;
 
 #[derive(Clone)]
 #[derive(Debug)]
 pub enum Rewrite {
     Last(Regex, String),
     Break(Regex, String),
+    Redirect(Regex, String, StatusCode),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RewriteRaw {
+    Text(String),
+    Table {
+        #[serde(rename = "match")]
+        regex: String,
+        replace: String,
+        flag: Option<String>,
+    },
 }
 
 #[allow(dead_code)]
 impl Rewrite {
     pub fn new(rewrite: String, path: &str) -> anyhow::Result<Rewrite> {
-        let parts = rewrite.split(' ').collect::<Vec<&str>>();
-        if parts.len() != 3 {
+        let parts = tokenize(&rewrite)
+            .map_err(|err| anyhow!("{} Wrong syntax: rewrite = {}, {}", path, rewrite, err))?;
+        if parts.len() != 2 && parts.len() != 3 {
             Err(anyhow!("{} Wrong syntax: rewrite = {}", path, rewrite))?;
         }
-        let regex = Regex::new(parts[0]).map_err(|err| anyhow!("{} {}", path, err.to_string()))?;
-        match parts[2] {
-            "last" => Ok(Rewrite::Last(regex, String::from(parts[1]))),
-            "break" => Ok(Rewrite::Break(regex, String::from(parts[1]))),
-            _ => Err(anyhow!("{} Wrong syntax: rewrite = {}", path, rewrite))?,
+        let flag = parts.get(2).map(|f| f.as_str()).unwrap_or("last");
+        Rewrite::with_flag(&parts[0], &parts[1], flag, path)
+            .map_err(|err| anyhow!("{} Wrong syntax: rewrite = {}, {}", path, rewrite, err))
+    }
+
+    pub fn from_raw(raw: RewriteRaw, path: &str) -> anyhow::Result<Rewrite> {
+        match raw {
+            RewriteRaw::Text(rewrite) => Rewrite::new(rewrite, path),
+            RewriteRaw::Table {
+                regex,
+                replace,
+                flag,
+            } => Rewrite::with_flag(&regex, &replace, flag.as_deref().unwrap_or("last"), path)
+                .map_err(|err| anyhow!("{} Wrong syntax: rewrite, {}", path, err)),
+        }
+    }
+
+    fn with_flag(regex: &str, replace: &str, flag: &str, path: &str) -> anyhow::Result<Rewrite> {
+        let regex = Regex::new(regex).map_err(|err| anyhow!("{} {}", path, err.to_string()))?;
+        let replace = String::from(replace);
+        match flag {
+            "last" => Ok(Rewrite::Last(regex, replace)),
+            "break" => Ok(Rewrite::Break(regex, replace)),
+            "redirect" => Ok(Rewrite::Redirect(regex, replace, StatusCode::FOUND)),
+            "permanent" => Ok(Rewrite::Redirect(
+                regex,
+                replace,
+                StatusCode::MOVED_PERMANENTLY,
+            )),
+            "temporary_redirect" => Ok(Rewrite::Redirect(
+                regex,
+                replace,
+                StatusCode::TEMPORARY_REDIRECT,
+            )),
+            "permanent_redirect" => Ok(Rewrite::Redirect(
+                regex,
+                replace,
+                StatusCode::PERMANENT_REDIRECT,
+            )),
+            _ => Err(anyhow!("unknown flag {}", flag))?,
         }
     }
 
@@ -27,6 +79,7 @@ impl Rewrite {
         match self {
             Rewrite::Last(re, _) => re,
             Rewrite::Break(re, _) => re,
+            Rewrite::Redirect(re, _, _) => re,
         }
     }
 
@@ -34,6 +87,7 @@ impl Rewrite {
         match self {
             Rewrite::Last(_, replace) => replace,
             Rewrite::Break(_, replace) => replace,
+            Rewrite::Redirect(_, replace, _) => replace,
         }
     }
 
@@ -41,6 +95,7 @@ impl Rewrite {
         match self {
             Rewrite::Last(_, _) => true,
             Rewrite::Break(_, _) => false,
+            Rewrite::Redirect(_, _, _) => false,
         }
     }
 
@@ -48,6 +103,92 @@ impl Rewrite {
         match self {
             Rewrite::Last(_, _) => false,
             Rewrite::Break(_, _) => true,
+            Rewrite::Redirect(_, _, _) => false,
         }
     }
+
+    pub fn redirect_status(&self) -> Option<StatusCode> {
+        match self {
+            Rewrite::Redirect(_, _, status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rewrite, RewriteRaw};
+    use http::StatusCode;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Config {
+        rewrite: Vec<RewriteRaw>,
+    }
+
+    fn parse(rewrite: &str) -> anyhow::Result<Rewrite> {
+        Rewrite::new(String::from(rewrite), "test.toml")
+    }
+
+    fn apply(rewrite: &Rewrite, uri: &str) -> String {
+        rewrite
+            .regex_as_ref()
+            .replace_all(uri, rewrite.replace_as_ref())
+            .to_string()
+    }
+
+    #[test]
+    fn string_syntax() {
+        let rewrite = parse("^/(.*) /service2/$1 break").unwrap();
+        assert!(rewrite.is_break());
+        assert_eq!(apply(&rewrite, "/a"), "/service2/a");
+
+        let rewrite = parse("^/(.*) /service2/$1").unwrap();
+        assert!(rewrite.is_last());
+
+        let rewrite = parse(r#""^/a b/(\d+)$" '/c d/$1' permanent"#).unwrap();
+        assert_eq!(
+            rewrite.redirect_status(),
+            Some(StatusCode::MOVED_PERMANENTLY)
+        );
+        assert_eq!(apply(&rewrite, "/a b/42"), "/c d/42");
+    }
+
+    #[test]
+    fn wrong_syntax() {
+        assert!(parse("^/(.*)").is_err());
+        assert!(parse("^/(.*) /a last extra").is_err());
+        assert!(parse("^/(.*) /a unknown").is_err());
+        assert!(parse("( /a last").is_err());
+        assert!(parse("\"^/(.*) /a last").is_err());
+    }
+
+    #[test]
+    fn table_syntax() {
+        let config: Config = toml::from_str(
+            r#"
+            rewrite = [
+                "^/old/(.*) /new/$1 redirect",
+                { match = "^/a b/(.*)$", replace = "/c/$1", flag = "break" },
+                { match = "^/(.*)$", replace = "/d/$1" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let rewrite = config
+            .rewrite
+            .into_iter()
+            .map(|r| Rewrite::from_raw(r, "test.toml"))
+            .collect::<anyhow::Result<Vec<Rewrite>>>()
+            .unwrap();
+        assert_eq!(rewrite[0].redirect_status(), Some(StatusCode::FOUND));
+        assert!(rewrite[1].is_break());
+        assert_eq!(apply(&rewrite[1], "/a b/e"), "/c/e");
+        assert!(rewrite[2].is_last());
+
+        let config: Config =
+            toml::from_str(r#"rewrite = [{ match = "^/", replace = "/", flag = "x" }]"#).unwrap();
+        let raw = config.rewrite.into_iter().next().unwrap();
+        assert!(Rewrite::from_raw(raw, "test.toml").is_err());
+    }
 }
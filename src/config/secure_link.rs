@@ -0,0 +1,52 @@
+use crate::util::path;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+
+/// Signed and expiring urls, like `secure_link` of nginx.
+#[derive(Clone)]
+pub struct SecureLink {
+    pub secret: Vec<u8>,
+    /// sign the client address as well
+    pub bind_ip: bool,
+    pub expires_param: String,
+    pub signature_param: String,
+}
+
+impl Debug for SecureLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecureLink")
+            .field("bind_ip", &self.bind_ip)
+            .field("expires_param", &self.expires_param)
+            .field("signature_param", &self.signature_param)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SecureLinkRaw {
+    pub secret_file: String,
+    pub bind_ip: Option<bool>,
+    pub expires_param: Option<String>,
+    pub signature_param: Option<String>,
+}
+
+impl SecureLink {
+    pub fn from_raw(raw: SecureLinkRaw, path: &str) -> anyhow::Result<Self> {
+        let file = path::resolve(path, &raw.secret_file);
+        let secret = fs::read(&file)
+            .map_err(|e| anyhow!("{} Failed to read {}: {}", path, file, e))?
+            .trim_ascii_end()
+            .to_vec();
+        if secret.is_empty() {
+            Err(anyhow!("{} Wrong syntax: secret {} is empty", path, file))?;
+        }
+        Ok(Self {
+            secret,
+            bind_ip: raw.bind_ip.unwrap_or_default(),
+            expires_param: raw.expires_param.unwrap_or(String::from("expires")),
+            signature_param: raw.signature_param.unwrap_or(String::from("signature")),
+        })
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::anyhow;
+use http::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const BASIC: [(&str, &str); 3] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "SAMEORIGIN"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+];
+
+const STRICT: [(&str, &str); 7] = [
+    (
+        "Content-Security-Policy",
+        "default-src 'self'; object-src 'none'; base-uri 'self'; frame-ancestors 'none'; form-action 'self'",
+    ),
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "DENY"),
+    ("Referrer-Policy", "no-referrer"),
+    (
+        "Permissions-Policy",
+        "camera=(), microphone=(), geolocation=(), payment=(), usb=()",
+    ),
+    ("Cross-Origin-Opener-Policy", "same-origin"),
+    ("Cross-Origin-Resource-Policy", "same-origin"),
+];
+
+/// Headers added to responses lacking them.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum SecurityHeadersRaw {
+    Preset(String),
+    Table {
+        preset: Option<String>,
+        headers: Option<HashMap<String, String>>,
+    },
+}
+
+impl SecurityHeaders {
+    pub fn from_raw(raw: SecurityHeadersRaw, path: &str) -> anyhow::Result<Self> {
+        let (preset, overrides) = match raw {
+            SecurityHeadersRaw::Preset(preset) => (Some(preset), HashMap::new()),
+            SecurityHeadersRaw::Table { preset, headers } => (preset, headers.unwrap_or_default()),
+        };
+        let mut headers: Vec<(String, String)> = match preset.as_deref() {
+            None => Vec::new(),
+            Some("basic") => BASIC
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            Some("strict") => STRICT
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            Some(preset) => Err(anyhow!(
+                "{} Wrong syntax: security_headers = {}, should be basic or strict",
+                path,
+                preset
+            ))?,
+        };
+        // an empty value removes the header of the preset
+        for (name, value) in overrides {
+            headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+            if !value.is_empty() {
+                headers.push((name, value));
+            }
+        }
+        let mut result = Vec::new();
+        for (name, value) in headers {
+            result.push((
+                HeaderName::from_str(&name)
+                    .map_err(|_| anyhow!("{} Wrong syntax: header {}", path, name))?,
+                HeaderValue::from_str(&value)
+                    .map_err(|_| anyhow!("{} Wrong syntax: header {} = {}", path, name, value))?,
+            ));
+        }
+        Ok(Self { headers: result })
+    }
+}
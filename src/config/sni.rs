@@ -0,0 +1,94 @@
+use crate::config::tokenize;
+use anyhow::anyhow;
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Clone, Debug)]
+pub enum Sni {
+    Exact(String),
+    /// `*.example.com`, stores `.example.com`
+    Suffix(String),
+    /// `www.example.*`, stores `www.example.`
+    Prefix(String),
+    Regex(Regex),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum SniRaw {
+    One(String),
+    List(Vec<String>),
+}
+
+impl SniRaw {
+    /// Normalized sni patterns, an empty list stands for the default one.
+    pub fn into_list(raw: Option<SniRaw>, path: &str) -> anyhow::Result<Vec<String>> {
+        let list = match raw {
+            None => vec![],
+            Some(SniRaw::One(sni)) => vec![sni],
+            Some(SniRaw::List(list)) => list,
+        };
+        list.into_iter()
+            .map(|sni| {
+                let sni = if sni.starts_with('~') {
+                    sni
+                } else {
+                    sni.to_lowercase()
+                };
+                Sni::new(&sni, path)?;
+                Ok(sni)
+            })
+            .collect()
+    }
+}
+
+impl Sni {
+    pub fn new(sni: &str, path: &str) -> anyhow::Result<Self> {
+        if sni.starts_with('~') {
+            let parts = tokenize(sni)
+                .map_err(|err| anyhow!("{} Wrong syntax: sni = {}, {}", path, sni, err))?;
+            if parts.len() != 2 || parts[0] != "~" {
+                Err(anyhow!("{} Wrong syntax: sni = {}", path, sni))?;
+            }
+            let re = Regex::new(&format!("(?i){}", parts[1]))
+                .map_err(|err| anyhow!("{} {}", path, err))?;
+            Ok(Sni::Regex(re))
+        } else if let Some(suffix) = sni.strip_prefix('*') {
+            if !suffix.starts_with('.') || suffix.contains('*') {
+                Err(anyhow!("{} Wrong syntax: sni = {}", path, sni))?;
+            }
+            Ok(Sni::Suffix(String::from(suffix)))
+        } else if let Some(prefix) = sni.strip_suffix('*') {
+            if !prefix.ends_with('.') || prefix.contains('*') {
+                Err(anyhow!("{} Wrong syntax: sni = {}", path, sni))?;
+            }
+            Ok(Sni::Prefix(String::from(prefix)))
+        } else if sni.contains('*') {
+            Err(anyhow!("{} Wrong syntax: sni = {}", path, sni))?
+        } else {
+            Ok(Sni::Exact(String::from(sni)))
+        }
+    }
+
+    /// Match a lowercased host without port, returns the regex captures named like `sni_1`
+    /// and `sni_<name>`.
+    pub fn captures(&self, host: &str) -> Option<Vec<(String, String)>> {
+        match self {
+            Sni::Exact(sni) => (sni == host).then(Vec::new),
+            Sni::Suffix(suffix) => host.ends_with(suffix.as_str()).then(Vec::new),
+            Sni::Prefix(prefix) => host.starts_with(prefix.as_str()).then(Vec::new),
+            Sni::Regex(re) => re.captures(host).map(|captures| {
+                let mut result = Vec::new();
+                for (i, name) in re.capture_names().enumerate().skip(1) {
+                    if let Some(m) = captures.get(i) {
+                        result.push((format!("sni_{}", i), String::from(m.as_str())));
+                        if let Some(name) = name {
+                            result.push((format!("sni_{}", name), String::from(m.as_str())));
+                        }
+                    }
+                }
+                result
+            }),
+        }
+    }
+}
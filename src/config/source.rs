@@ -1,12 +1,21 @@
-use crate::config::{Location, Proxy, ProxyRaw, Rewrite, StaticServer, StaticServerRaw};
+use crate::config::{
+    parse_header_names, Access, AccessRaw, AuthBasic, AuthBasicRaw, ClientTimeouts, Condition,
+    ConditionRaw, Cors, CorsRaw, ErrorPageRaw, ErrorPages, ForwardAuth, ForwardAuthRaw, HeaderRule,
+    HeaderRuleRaw, Importable, Jwt, JwtRaw, Location, LocationRaw, Proxy, ProxyRaw, RateLimit,
+    RateLimitRaw, Return, ReturnRaw, Rewrite, RewriteRaw, SecurityHeaders, SecurityHeadersRaw,
+    SniRaw, StaticServer, StaticServerRaw, Waf, WafRaw,
+};
+use indexmap::IndexMap;
 use serde::de::{Error, IntoDeserializer};
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 use toml::Value;
 
+#[allow(clippy::large_enum_variant)]
 pub enum SourceRaw {
     Proxy(ProxyRaw),
     Static(StaticServerRaw),
+    Return(ReturnRaw),
 }
 
 #[derive(Deserialize)]
@@ -82,6 +91,31 @@ impl<'de> Deserialize<'de> for SourceRaw {
                 };
             }
         };
+        let v = ReturnRaw::deserialize(value.clone().into_deserializer());
+        match v {
+            Ok(i) => {
+                match &i.source_type {
+                    None => {
+                        return Ok(SourceRaw::Return(i));
+                    }
+                    Some(s) => {
+                        if s.to_lowercase() == "return" {
+                            return Ok(SourceRaw::Return(i));
+                        }
+                    }
+                };
+            }
+            Err(e) => {
+                match &mat {
+                    None => err = Some(merge_err(err, e)),
+                    Some(v) => {
+                        if v == "return" {
+                            err = Some(merge_err(err, e))
+                        }
+                    }
+                };
+            }
+        };
         return match err {
             None => Err(Error::custom("unknown source type")),
             Some(err) => Err(Error::custom(err.message())),
@@ -94,6 +128,7 @@ impl<'de> Deserialize<'de> for SourceRaw {
 pub enum Source {
     Proxy(Proxy),
     Static(StaticServer),
+    Return(Return),
 }
 
 #[allow(dead_code)]
@@ -112,62 +147,236 @@ impl Source {
                 check_duration,
             )?)),
             SourceRaw::Static(i) => Ok(Source::Static(StaticServer::from_raw(i, path)?)),
+            SourceRaw::Return(i) => Ok(Source::Return(Return::from_raw(i, path)?)),
         }
     }
 
-    pub fn sni_as_ref(&self) -> &Option<String> {
+    pub fn options(&self) -> &SourceOptions {
         match self {
-            Source::Proxy(p) => &p.sni,
-            Source::Static(s) => &s.sni,
+            Source::Proxy(p) => &p.options,
+            Source::Static(s) => &s.options,
+            Source::Return(r) => &r.options,
         }
     }
 
-    pub fn location_as_ref(&self) -> &Vec<Location> {
+    pub fn is_proxy(&self) -> bool {
         match self {
-            Source::Proxy(p) => &p.location,
-            Source::Static(s) => &s.location,
+            Source::Proxy(_) => true,
+            Source::Static(_) => false,
+            Source::Return(_) => false,
         }
     }
 
-    pub fn rewrite_as_ref(&self) -> &Option<Vec<Rewrite>> {
+    pub fn is_static(&self) -> bool {
         match self {
-            Source::Proxy(p) => &p.rewrite,
-            Source::Static(s) => &s.rewrite,
+            Source::Proxy(_) => false,
+            Source::Static(_) => true,
+            Source::Return(_) => false,
         }
     }
 
-    pub fn fallback_as_ref(&self) -> &Vec<String> {
+    pub fn is_return(&self) -> bool {
         match self {
-            Source::Proxy(p) => &p.fallback,
-            Source::Static(s) => &s.fallback,
+            Source::Proxy(_) => false,
+            Source::Static(_) => false,
+            Source::Return(_) => true,
         }
     }
+}
 
-    pub fn headers_request_as_ref(&self) -> &Option<HashMap<String, String>> {
-        match self {
-            Source::Proxy(p) => &p.headers_request,
-            Source::Static(s) => &s.headers_request,
-        }
-    }
+/// Options of routing, access and responses, shared by every source type.
+#[derive(Clone, Debug)]
+pub struct SourceOptions {
+    pub sni: Vec<String>,
+    pub location: Vec<Location>,
+    pub rewrite: Option<Vec<Rewrite>>,
+    pub fallback: Vec<String>,
+    pub condition: Option<Condition>,
+    pub headers_response: Option<Vec<HeaderRule>>,
+    pub headers_response_remove: Vec<String>,
+    pub error_page: ErrorPages,
+    pub access: Option<Access>,
+    pub auth_basic: Option<AuthBasic>,
+    pub forward_auth: Option<ForwardAuth>,
+    pub jwt: Option<Jwt>,
+    pub rate_limit: Vec<RateLimit>,
+    pub timeouts: ClientTimeouts,
+    pub cors: Option<Cors>,
+    pub security_headers: Option<SecurityHeaders>,
+    pub waf: Option<Waf>,
+}
 
-    pub fn headers_response_as_ref(&self) -> &Option<HashMap<String, String>> {
-        match self {
-            Source::Proxy(p) => &p.headers_response,
-            Source::Static(s) => &s.headers_response,
-        }
+#[derive(Deserialize)]
+pub struct SourceOptionsRaw {
+    pub sni: Option<SniRaw>,
+    pub location: Option<Vec<LocationRaw>>,
+    pub rewrite: Option<Vec<RewriteRaw>>,
+    pub fallback: Option<Vec<String>>,
+    pub condition: Option<ConditionRaw>,
+    pub headers_response: Option<Importable<IndexMap<String, HeaderRuleRaw>>>,
+    pub headers_response_remove: Option<Vec<String>>,
+    pub error_page: Option<HashMap<String, ErrorPageRaw>>,
+    pub access: Option<Importable<AccessRaw>>,
+    pub auth_basic: Option<AuthBasicRaw>,
+    pub forward_auth: Option<ForwardAuthRaw>,
+    pub jwt: Option<JwtRaw>,
+    pub rate_limit: Option<Vec<RateLimitRaw>>,
+    pub client_body_timeout: Option<u64>,
+    pub keepalive_timeout: Option<u64>,
+    pub send_timeout: Option<u64>,
+    pub cors: Option<CorsRaw>,
+    pub security_headers: Option<SecurityHeadersRaw>,
+    pub waf: Option<WafRaw>,
+}
+
+impl SourceOptions {
+    pub fn from_raw(raw: SourceOptionsRaw, path: &str) -> anyhow::Result<Self> {
+        let sni = SniRaw::into_list(raw.sni, path)?;
+        let location = match raw.location {
+            None => vec![Location::Start(String::from("/"))],
+            Some(loc) => {
+                let mut result: Vec<Location> = Vec::new();
+                for location in loc {
+                    result.push(Location::from_raw(location, path)?)
+                }
+                result
+            }
+        };
+        let rewrite = match raw.rewrite {
+            None => None,
+            Some(list) => Some({
+                let mut vec: Vec<Rewrite> = Vec::new();
+                list.into_iter().try_for_each(|v| -> anyhow::Result<()> {
+                    vec.push(Rewrite::from_raw(v, path)?);
+                    Ok(())
+                })?;
+                vec
+            }),
+        };
+        let condition = match raw.condition {
+            Some(c) => Some(Condition::from_raw(c, path)?),
+            None => None,
+        };
+        let headers_response = match raw.headers_response {
+            Some(h) => Some(HeaderRule::from_map(h.import(path)?.0, true, path)?),
+            None => None,
+        };
+        let headers_response_remove =
+            parse_header_names(raw.headers_response_remove.unwrap_or_default(), path)?;
+        let error_page = ErrorPages::from_raw(raw.error_page.unwrap_or_default(), path)?;
+        let access = match raw.access {
+            Some(a) => Some(Access::from_raw(a, path)?),
+            None => None,
+        };
+        let auth_basic = match raw.auth_basic {
+            Some(a) => Some(AuthBasic::from_raw(a, path)?),
+            None => None,
+        };
+        let forward_auth = match raw.forward_auth {
+            Some(f) => Some(ForwardAuth::from_raw(f, path)?),
+            None => None,
+        };
+        let jwt = match raw.jwt {
+            Some(j) => Some(Jwt::from_raw(j, path)?),
+            None => None,
+        };
+        let rate_limit = RateLimit::from_list(raw.rate_limit.unwrap_or_default(), path)?;
+        let timeouts = ClientTimeouts::from_raw(
+            None,
+            raw.client_body_timeout,
+            raw.keepalive_timeout,
+            None,
+            raw.send_timeout,
+            path,
+        )?;
+        let cors = match raw.cors {
+            Some(c) => Some(Cors::from_raw(c, path)?),
+            None => None,
+        };
+        let security_headers = match raw.security_headers {
+            Some(h) => Some(SecurityHeaders::from_raw(h, path)?),
+            None => None,
+        };
+        let waf = match raw.waf {
+            Some(waf) => Some(Waf::from_raw(waf, path)?),
+            None => None,
+        };
+        Ok(Self {
+            sni,
+            location,
+            rewrite,
+            fallback: raw.fallback.unwrap_or_default(),
+            condition,
+            headers_response,
+            headers_response_remove,
+            error_page,
+            access,
+            auth_basic,
+            forward_auth,
+            jwt,
+            rate_limit,
+            timeouts,
+            cors,
+            security_headers,
+            waf,
+        })
     }
+}
 
-    pub fn is_proxy(&self) -> bool {
-        match self {
-            Source::Proxy(_) => true,
-            Source::Static(_) => false,
-        }
+#[cfg(test)]
+mod tests {
+    use super::{Source, SourceRaw};
+    use std::time::Duration;
+
+    fn parse(config: &str) -> Source {
+        let raw: SourceRaw = toml::from_str(config).unwrap();
+        Source::from_raw(raw, "test.toml", false, 0).unwrap()
     }
 
-    pub fn is_static(&self) -> bool {
-        match self {
-            Source::Proxy(_) => false,
-            Source::Static(_) => true,
-        }
+    #[test]
+    fn shared_options_of_each_type() {
+        let proxy = parse(
+            r#"
+            ip = "127.0.0.1"
+            port = 8080
+            ssl = false
+            sni = "a.com"
+            headers_request = { "X-A" = "b" }
+            send_timeout = 3000
+            "#,
+        );
+        assert!(matches!(&proxy, Source::Proxy(p) if p.headers_request.is_some()));
+        assert_eq!(proxy.options().sni, vec![String::from("a.com")]);
+        assert_eq!(proxy.options().timeouts.send, Some(Duration::from_secs(3)));
+
+        let static_server = parse(
+            r#"
+            source_type = "static"
+            root = "/srv"
+            fallback = ["proxy"]
+            keepalive_timeout = 0
+            "#,
+        );
+        assert!(static_server.is_static());
+        assert_eq!(
+            static_server.options().fallback,
+            vec![String::from("proxy")]
+        );
+        assert_eq!(
+            static_server.options().timeouts.keepalive,
+            Some(Duration::ZERO)
+        );
+
+        let ret = parse(
+            r#"
+            source_type = "return"
+            status = 204
+            location = ["= /health"]
+            headers_response = { "Cache-Control" = "no-store" }
+            "#,
+        );
+        assert!(ret.is_return());
+        assert_eq!(ret.options().location.len(), 1);
+        assert!(ret.options().headers_response.is_some());
     }
 }
@@ -0,0 +1,91 @@
+/// Split a config directive like `~ "^/a b/(.*)$"` into whitespace separated tokens.
+///
+/// Single quotes keep everything literally. In double quotes and bare words, `\"`, `\'` and
+/// `\ ` escape the quote or the space; other backslashes are kept as is, so regexes like
+/// `\d+` need no extra escaping.
+pub fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(t) = token.take() {
+                    tokens.push(t);
+                }
+            }
+            '\'' => {
+                let t = token.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("unterminated quote")),
+                        Some('\'') => break,
+                        Some(c) => t.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let t = token.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("unterminated quote")),
+                        Some('"') => break,
+                        Some('\\') => push_escaped(t, chars.next()),
+                        Some(c) => t.push(c),
+                    }
+                }
+            }
+            '\\' => push_escaped(token.get_or_insert_with(String::new), chars.next()),
+            c => token.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(t) = token {
+        tokens.push(t);
+    }
+    Ok(tokens)
+}
+
+fn push_escaped(token: &mut String, c: Option<char>) {
+    match c {
+        Some(c @ ('"' | '\'' | ' ')) => token.push(c),
+        Some(c) => {
+            token.push('\\');
+            token.push(c);
+        }
+        None => token.push('\\'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokenize;
+
+    #[test]
+    fn split_on_whitespace() {
+        assert_eq!(
+            tokenize("^/(.*)  /service2/$1\tlast").unwrap(),
+            vec!["^/(.*)", "/service2/$1", "last"]
+        );
+        assert!(tokenize("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            tokenize(r#"= "/a b" '/c "d"'"#).unwrap(),
+            vec!["=", "/a b", r#"/c "d""#]
+        );
+        assert_eq!(tokenize(r#"/a"b c"d"#).unwrap(), vec!["/ab cd"]);
+        assert_eq!(tokenize(r#""""#).unwrap(), vec![""]);
+        assert!(tokenize(r#""/a"#).is_err());
+        assert!(tokenize("'/a").is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(tokenize(r"/a\ b").unwrap(), vec!["/a b"]);
+        assert_eq!(tokenize(r#""a\"b""#).unwrap(), vec![r#"a"b"#]);
+        assert_eq!(tokenize(r"^/(\d+)\s\w").unwrap(), vec![r"^/(\d+)\s\w"]);
+        assert_eq!(tokenize(r"'\ '").unwrap(), vec![r"\ "]);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::util::path;
+use anyhow::anyhow;
+use pingora::tls::pkey::PKey;
+use pingora::tls::x509::X509;
+use pingora::utils::tls::CertKey;
+use serde::Deserialize;
+use std::fs;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct UpstreamTls {
+    pub verify_cert: bool,
+    pub verify_hostname: bool,
+    pub sni: Option<String>,
+    pub alternative_cn: Option<String>,
+    pub ca: Option<Arc<Box<[X509]>>>,
+    pub client_cert_key: Option<Arc<CertKey>>,
+}
+
+#[derive(Deserialize)]
+pub struct UpstreamTlsRaw {
+    pub verify_cert: Option<bool>,
+    pub verify_hostname: Option<bool>,
+    pub sni: Option<String>,
+    pub alternative_cn: Option<String>,
+    pub ca: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+fn read_file(base: &str, file: &str) -> anyhow::Result<(Vec<u8>, String)> {
+    let file = path::resolve(base, file);
+    let content = fs::read(&file).or(Err(anyhow!("Cannot read file {}", &file)))?;
+    Ok((content, file))
+}
+
+impl UpstreamTls {
+    pub fn from_raw(raw: UpstreamTlsRaw, path: &str) -> anyhow::Result<Self> {
+        let ca = match raw.ca {
+            None => None,
+            Some(ca) => {
+                let (pem, ca) = read_file(path, &ca)?;
+                let certs = X509::stack_from_pem(&pem)
+                    .map_err(|err| anyhow!("Failed to parse ca {}: {}", &ca, err))?;
+                if certs.is_empty() {
+                    Err(anyhow!("No certificate found in ca {}", &ca))?;
+                }
+                Some(Arc::new(certs.into_boxed_slice()))
+            }
+        };
+        let client_cert_key = match (raw.cert, raw.key) {
+            (None, None) => None,
+            (Some(cert), Some(key)) => {
+                let (cert_pem, cert) = read_file(path, &cert)?;
+                let (key_pem, key) = read_file(path, &key)?;
+                let certs = X509::stack_from_pem(&cert_pem)
+                    .map_err(|err| anyhow!("Failed to parse cert {}: {}", &cert, err))?;
+                if certs.is_empty() {
+                    Err(anyhow!("No certificate found in cert {}", &cert))?;
+                }
+                let key = PKey::private_key_from_pem(&key_pem)
+                    .map_err(|err| anyhow!("Failed to parse key {}: {}", &key, err))?;
+                Some(Arc::new(CertKey::new(certs, key)))
+            }
+            _ => Err(anyhow!(
+                "{} Wrong syntax: upstream_tls.cert and upstream_tls.key must be set together",
+                path
+            ))?,
+        };
+        Ok(Self {
+            verify_cert: raw.verify_cert.unwrap_or(true),
+            verify_hostname: raw.verify_hostname.unwrap_or(true),
+            sni: raw.sni,
+            alternative_cn: raw.alternative_cn,
+            ca,
+            client_cert_key,
+        })
+    }
+}
@@ -0,0 +1,259 @@
+use crate::config::{Importable, SizeRaw};
+use crate::util::path;
+use crate::util::waf::RuleFile;
+use anyhow::anyhow;
+use http::{HeaderName, StatusCode};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Probes of SQL injection, XSS and path traversal, added after the rules of the user.
+pub const DEFAULT_RULES: &str = include_str!("waf_rules.toml");
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WafTarget {
+    Method,
+    /// percent-decoded
+    Path,
+    /// percent-decoded, `+` as space
+    Query,
+    UserAgent,
+    Header(HeaderName),
+    /// first `body_limit` bytes, proxy sources only
+    Body,
+}
+
+#[derive(Clone, Debug)]
+pub enum WafPattern {
+    /// lowercase when `ignore_case` is set
+    Contains(String),
+    Regex(Regex),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WafAction {
+    Deny,
+    Log,
+    Tag(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct WafRule {
+    pub id: String,
+    pub targets: Vec<WafTarget>,
+    pub pattern: WafPattern,
+    pub ignore_case: bool,
+    pub action: WafAction,
+    /// status of the response when denied
+    pub status: StatusCode,
+}
+
+/// Rules checked on requests, the ones of a file are reloaded when it is modified.
+#[derive(Clone, Debug)]
+pub struct Waf {
+    pub rules: Arc<RuleFile>,
+    /// bytes of the request body inspected
+    pub body_limit: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum WafTargetRaw {
+    One(String),
+    List(Vec<String>),
+}
+
+#[derive(Deserialize)]
+pub struct WafRuleRaw {
+    pub id: String,
+    pub target: WafTargetRaw,
+    pub contains: Option<String>,
+    pub regex: Option<String>,
+    pub ignore_case: Option<bool>,
+    pub action: Option<String>,
+    pub tag: Option<String>,
+    pub status: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum WafRulesRaw {
+    List(Vec<WafRuleRaw>),
+    Table { rules: Vec<WafRuleRaw> },
+}
+
+#[derive(Deserialize)]
+pub struct WafRaw {
+    pub rules: Option<Importable<WafRulesRaw>>,
+    pub default_rules: Option<bool>,
+    pub body_limit: Option<SizeRaw>,
+}
+
+impl WafTarget {
+    /// `method`, `path`, `query`, `user_agent`, `header NAME` or `body`.
+    fn new(target: &str, path: &str) -> anyhow::Result<Self> {
+        match target.split_once(' ') {
+            None if target == "method" => Ok(WafTarget::Method),
+            None if target == "path" => Ok(WafTarget::Path),
+            None if target == "query" => Ok(WafTarget::Query),
+            None if target == "user_agent" => Ok(WafTarget::UserAgent),
+            None if target == "body" => Ok(WafTarget::Body),
+            Some(("header", name)) => Ok(WafTarget::Header(
+                HeaderName::from_str(name.trim())
+                    .map_err(|_| anyhow!("{} Wrong syntax: waf rule target = {}", path, target))?,
+            )),
+            _ => Err(anyhow!(
+                "{} Wrong syntax: waf rule target = {}",
+                path,
+                target
+            )),
+        }
+    }
+}
+
+impl Display for WafTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WafTarget::Method => write!(f, "method"),
+            WafTarget::Path => write!(f, "path"),
+            WafTarget::Query => write!(f, "query"),
+            WafTarget::UserAgent => write!(f, "user_agent"),
+            WafTarget::Header(name) => write!(f, "header {}", name),
+            WafTarget::Body => write!(f, "body"),
+        }
+    }
+}
+
+impl WafAction {
+    pub fn as_str(&self) -> &str {
+        match self {
+            WafAction::Deny => "deny",
+            WafAction::Log => "log",
+            WafAction::Tag(_) => "tag",
+        }
+    }
+}
+
+impl WafRule {
+    pub fn from_raw(raw: WafRuleRaw, path: &str) -> anyhow::Result<Self> {
+        let id = raw.id;
+        let targets = match raw.target {
+            WafTargetRaw::One(target) => vec![target],
+            WafTargetRaw::List(targets) => targets,
+        };
+        if targets.is_empty() {
+            Err(anyhow!(
+                "{} Wrong syntax: waf rule {} has no target",
+                path,
+                id
+            ))?;
+        }
+        let targets = targets
+            .iter()
+            .map(|target| WafTarget::new(target.trim(), path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let ignore_case = raw.ignore_case.unwrap_or(false);
+        let pattern = match (raw.contains, raw.regex) {
+            (Some(contains), None) if ignore_case => WafPattern::Contains(contains.to_lowercase()),
+            (Some(contains), None) => WafPattern::Contains(contains),
+            (None, Some(regex)) => WafPattern::Regex(
+                RegexBuilder::new(&regex)
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .map_err(|e| anyhow!("{} Wrong syntax: waf rule {}, {}", path, id, e))?,
+            ),
+            _ => Err(anyhow!(
+                "{} Wrong syntax: waf rule {} should have one of contains and regex",
+                path,
+                id
+            ))?,
+        };
+        let action = match (raw.action.as_deref().unwrap_or("deny"), raw.tag) {
+            ("deny", None) => WafAction::Deny,
+            ("log", None) => WafAction::Log,
+            ("tag", Some(tag)) if !tag.is_empty() => WafAction::Tag(tag),
+            ("tag", _) => Err(anyhow!(
+                "{} Wrong syntax: waf rule {} requires tag",
+                path,
+                id
+            ))?,
+            (action, None) => Err(anyhow!(
+                "{} Wrong syntax: waf rule {} action = {}, should be deny, log or tag",
+                path,
+                id,
+                action
+            ))?,
+            (_, Some(_)) => Err(anyhow!(
+                "{} Wrong syntax: waf rule {} has tag, but action is not tag",
+                path,
+                id
+            ))?,
+        };
+        let status = match raw.status {
+            Some(status) => StatusCode::from_u16(status)
+                .ok()
+                .filter(|status| status.as_u16() >= 400)
+                .ok_or(anyhow!(
+                    "{} Wrong syntax: waf rule {} status = {}",
+                    path,
+                    id,
+                    status
+                ))?,
+            None => StatusCode::FORBIDDEN,
+        };
+        Ok(Self {
+            id,
+            targets,
+            pattern,
+            ignore_case,
+            action,
+            status,
+        })
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        match &self.pattern {
+            WafPattern::Contains(contains) if self.ignore_case => {
+                value.to_lowercase().contains(contains.as_str())
+            }
+            WafPattern::Contains(contains) => value.contains(contains.as_str()),
+            WafPattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+pub fn parse_waf_rules(raw: WafRulesRaw, path: &str) -> anyhow::Result<Vec<WafRule>> {
+    let rules = match raw {
+        WafRulesRaw::List(rules) => rules,
+        WafRulesRaw::Table { rules } => rules,
+    };
+    rules
+        .into_iter()
+        .map(|rule| WafRule::from_raw(rule, path))
+        .collect()
+}
+
+impl Waf {
+    pub fn from_raw(raw: WafRaw, path: &str) -> anyhow::Result<Self> {
+        let default_rules = raw.default_rules.unwrap_or(true);
+        let rules = match raw.rules {
+            Some(Importable::Import(file)) => {
+                RuleFile::load(path::resolve(path, &file), default_rules)?
+            }
+            Some(Importable::Some(rules)) => {
+                RuleFile::fixed(parse_waf_rules(rules, path)?, default_rules)
+            }
+            None => RuleFile::fixed(Vec::new(), default_rules),
+        };
+        let body_limit = match raw.body_limit {
+            Some(size) => size.parse("waf.body_limit", path)?,
+            None => 8 << 10,
+        };
+        Ok(Self {
+            rules: Arc::new(rules),
+            body_limit,
+        })
+    }
+}
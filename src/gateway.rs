@@ -1,46 +1,489 @@
-use crate::config::{Proxy, Source};
-use crate::util::file_err::{make_page50x, PAGE404};
+use crate::config::{
+    keepalive_secs, AuthBasic, AuthEndpoint, ClientTimeouts, Concurrency, ErrorPages, ForwardAuth,
+    Hsts, Proxy, ProxyProtocolVersion, RateLimit, RateLimitKey, RealIp, RedirectHttps,
+    SecurityHeaders, Server, Source, UpstreamTls, Waf, WafAction, WafRule, WafTarget,
+};
+use crate::util::cors;
+use crate::util::file_err::builtin_page;
+use crate::util::forward_auth::{self, AuthResult};
+use crate::util::forwarded::{self, ForwardedInfo};
+use crate::util::headers;
+use crate::util::htpasswd;
+use crate::util::jwt;
+use crate::util::metrics::{self, InFlight};
 use crate::util::mime::get_mime_type;
-use crate::util::path;
+use crate::util::proxy_protocol::{self, ProxyProtocolConnect};
 use crate::util::route::*;
-use crate::util::url::encode_ignore_slash;
+use crate::util::secure_link::{self, LinkStatus};
+use crate::util::template;
+use crate::util::url::{decode_path, encode_uri};
+use crate::util::waf;
+use crate::util::{ip, path};
 use async_trait::async_trait;
-use http::{header, StatusCode, Uri};
+use bytes::Bytes;
+use http::{header, HeaderName, HeaderValue, StatusCode, Uri};
 use log::{debug, error, info};
+use pingora::connectors::http::Connector;
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::prelude::{HttpPeer, ProxyHttp, Session};
 use pingora::proxy::FailToProxy;
-use pingora::{Error, HTTPStatus};
-use std::collections::HashMap;
-use std::net::ToSocketAddrs;
+use pingora::{Error, ErrorSource, HTTPStatus, ReadTimedout};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
-use urlencoding::decode;
+use tokio::sync::OwnedSemaphorePermit;
+
+/// Keepalive of pingora for downstream connections (s).
+const DEFAULT_KEEPALIVE: u64 = 60;
 
 pub struct Gateway {
     port: u16,
-    routes: HashMap<String, HashMap<String, Source>>,
+    routes: Routes,
     check_status: bool,
+    redirect_https: Option<RedirectHttps>,
+    hsts: Option<Hsts>,
+    server_tokens: Option<String>,
+    error_page: ErrorPages,
+    real_ip: Option<RealIp>,
+    auth_connector: Connector,
+    concurrency: Option<Concurrency>,
+    timeouts: ClientTimeouts,
+    security_headers: Option<SecurityHeaders>,
+    waf: Option<Waf>,
 }
 
 impl Gateway {
-    pub fn new(
-        port: u16,
-        routes: HashMap<String, HashMap<String, Source>>,
-        check_status: bool,
-    ) -> Self {
+    pub fn new(port: u16, routes: Routes, server: &Server, server_tokens: Option<String>) -> Self {
         Self {
             port,
             routes,
-            check_status,
+            check_status: server.check_status,
+            redirect_https: server.redirect_https.clone(),
+            hsts: server.hsts.clone(),
+            server_tokens,
+            error_page: server.error_page.clone(),
+            real_ip: server.real_ip.clone(),
+            auth_connector: Connector::new(None),
+            concurrency: server.concurrency.clone(),
+            timeouts: server.timeouts.clone(),
+            security_headers: server.security_headers.clone(),
+            waf: server.waf.clone(),
+        }
+    }
+
+    fn source(&self, ctx: &GatewayCTX) -> Option<&Source> {
+        self.routes
+            .get(ctx.sni.as_ref()?)?
+            .get(ctx.source.as_ref()?)
+    }
+
+    fn insert_server_headers(
+        &self,
+        resp: &mut ResponseHeader,
+        ctx: &GatewayCTX,
+    ) -> pingora::Result<()> {
+        // replace any existing header
+        match &self.server_tokens {
+            Some(tokens) => resp.insert_header(header::SERVER, tokens)?,
+            None => {
+                resp.remove_header(&header::SERVER);
+            }
+        }
+        if let Some(hsts) = &self.hsts {
+            resp.insert_header(header::STRICT_TRANSPORT_SECURITY, hsts.header_value())?;
+        }
+        if let Some(headers) = &ctx.cors {
+            for name in &cors::RESPONSE_HEADERS {
+                resp.remove_header(name);
+            }
+            for (name, value) in headers {
+                resp.insert_header(name.clone(), value)?;
+            }
+            resp.append_header(header::VARY, "Origin")?;
+        }
+        if let Some((limit, remaining, reset)) = ctx.rate_limit {
+            resp.insert_header("RateLimit-Limit", limit.to_string())?;
+            resp.insert_header("RateLimit-Remaining", remaining.to_string())?;
+            resp.insert_header("RateLimit-Reset", reset.to_string())?;
+        }
+        // the ones of the source replace the ones of the server, upstream headers are kept
+        let security_headers = match self.source(ctx) {
+            Some(source) if source.options().security_headers.is_some() => {
+                &source.options().security_headers
+            }
+            _ => &self.security_headers,
+        };
+        if let Some(security_headers) = security_headers {
+            for (name, value) in &security_headers.headers {
+                if !resp.headers.contains_key(name) {
+                    resp.insert_header(name.clone(), value.clone())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The waf of the source, or else the one of the server.
+    fn waf(&self, ctx: &GatewayCTX) -> Option<&Waf> {
+        match self.source(ctx) {
+            Some(source) if source.options().waf.is_some() => source.options().waf.as_ref(),
+            _ => self.waf.as_ref(),
+        }
+    }
+
+    /// Apply the actions of matched rules in order, the status of the first `deny`.
+    fn check_waf<'a>(
+        &self,
+        ctx: &mut GatewayCTX,
+        rules: &[WafRule],
+        values: impl Fn(&WafTarget) -> Vec<Cow<'a, str>>,
+    ) -> Option<StatusCode> {
+        for rule in rules {
+            let target = match rule
+                .targets
+                .iter()
+                .find(|target| values(target).iter().any(|value| rule.is_match(value)))
+            {
+                Some(target) => target,
+                None => continue,
+            };
+            let source = ctx.source.as_deref().unwrap_or_default();
+            metrics::waf_matched(self.port, source, &rule.id, rule.action.as_str());
+            match &rule.action {
+                WafAction::Deny => {
+                    info!(
+                        "[{}.{}]: WAF rule {} on {} denied request from {}",
+                        self.port,
+                        source,
+                        rule.id,
+                        target,
+                        client_ip(ctx)
+                    );
+                    return Some(rule.status);
+                }
+                WafAction::Log => info!(
+                    "[{}.{}]: WAF rule {} matched {} of request from {}",
+                    self.port,
+                    source,
+                    rule.id,
+                    target,
+                    client_ip(ctx)
+                ),
+                WafAction::Tag(tag) => {
+                    info!(
+                        "[{}.{}]: WAF rule {} tagged {} on {} of request from {}",
+                        self.port,
+                        source,
+                        rule.id,
+                        tag,
+                        target,
+                        client_ip(ctx)
+                    );
+                    if !ctx.waf_tags.contains(tag) {
+                        ctx.waf_tags.push(tag.clone());
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Answer with the error page of the source, then the server, then the built-in one.
+    async fn error_page(
+        &self,
+        session: &mut Session,
+        ctx: &GatewayCTX,
+        status: StatusCode,
+    ) -> pingora::Result<bool> {
+        self.error_page_with_headers(session, ctx, status, Vec::new())
+            .await
+    }
+
+    async fn error_page_with_headers(
+        &self,
+        session: &mut Session,
+        ctx: &GatewayCTX,
+        status: StatusCode,
+        headers: Vec<(HeaderName, String)>,
+    ) -> pingora::Result<bool> {
+        let page = self
+            .source(ctx)
+            .and_then(|source| source.options().error_page.get(status))
+            .or(self.error_page.get(status));
+        let (body, content_type) = match page {
+            Some(page) if page.template => (
+                template::render(&page.body, |name| match name {
+                    "status" => Some(String::from(status.as_str())),
+                    _ => template::request_variable(session, ctx, name),
+                }),
+                page.content_type.clone(),
+            ),
+            Some(page) => (page.body.clone(), page.content_type.clone()),
+            None => (
+                builtin_page(status, self.server_tokens.as_deref() == Some("Pingpong")),
+                get_mime_type(".html"),
+            ),
+        };
+
+        let mut resp = ResponseHeader::build(status, Some(4))?;
+        self.insert_server_headers(&mut resp, ctx)?;
+        resp.insert_header(header::CONTENT_LENGTH, body.len().to_string())?;
+        resp.insert_header(header::CONTENT_TYPE, content_type)?;
+        for (name, value) in headers {
+            resp.insert_header(name, value)?;
+        }
+        session.write_response_header(Box::new(resp), false).await?;
+        session.write_response_body(Some(body.into()), true).await?;
+        Ok(true)
+    }
+
+    /// User of valid credentials in `Authorization`.
+    async fn auth_basic(&self, header: &RequestHeader, auth: &AuthBasic) -> Option<String> {
+        let (user, password) = header
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| htpasswd::basic_credentials(value.as_bytes()))?;
+        let user_file = auth.user_file.clone();
+        let verified =
+            tokio::task::spawn_blocking(move || user_file.verify(&user, &password).then_some(user))
+                .await;
+        match verified {
+            Ok(user) => user,
+            Err(e) => {
+                error!("[{}]: Failed to verify credentials: {}", self.port, e);
+                None
+            }
+        }
+    }
+
+    /// Peer of the auth service and `Host` of the subrequest.
+    fn auth_peer(
+        &self,
+        ctx: &GatewayCTX,
+        auth: &ForwardAuth,
+        host: &str,
+    ) -> Option<(Box<HttpPeer>, String)> {
+        match &auth.endpoint {
+            AuthEndpoint::Source(name) => {
+                let source = self.routes.get(ctx.sni.as_ref()?)?.get(name)?;
+                match source {
+                    Source::Proxy(proxy) => Some((
+                        self.peer(proxy),
+                        proxy.host.clone().unwrap_or(String::from(host)),
+                    )),
+                    Source::Static(_) | Source::Return(_) => None,
+                }
+            }
+            AuthEndpoint::Url {
+                addr,
+                host,
+                port,
+                ssl,
+            } => {
+                let mut peer = HttpPeer::new(*addr, *ssl, host.clone());
+                peer.options.connection_timeout = Some(Duration::new(3, 0));
+                let host = match (*ssl, *port) {
+                    (false, 80) | (true, 443) => host.clone(),
+                    _ if host.contains(':') => format!("[{}]:{}", host, port),
+                    _ => format!("{}:{}", host, port),
+                };
+                Some((Box::new(peer), host))
+            }
+        }
+    }
+
+    /// Ask the auth service, answer the request unless it is allowed.
+    async fn forward_auth(
+        &self,
+        session: &mut Session,
+        ctx: &mut GatewayCTX,
+        auth: &ForwardAuth,
+    ) -> pingora::Result<bool> {
+        let host = template::host(session);
+        let (peer, auth_host) = match self.auth_peer(ctx, auth, &host) {
+            Some(peer) => peer,
+            None => {
+                error!(
+                    "[{}]: Failed to find auth service {:?}",
+                    self.port, auth.endpoint
+                );
+                return self
+                    .error_page(session, ctx, StatusCode::INTERNAL_SERVER_ERROR)
+                    .await;
+            }
+        };
+        let original_uri = ctx.request_uri.clone().unwrap_or_default();
+        let info = ForwardedInfo {
+            peer_ip: ip::peer_ip(session),
+            client_ip: ctx.client_ip,
+            proto: template::scheme(session),
+            host: &host,
+            port: self.port,
+        };
+        let req = forward_auth::subrequest(
+            session.req_header(),
+            &original_uri,
+            auth,
+            &auth_host,
+            auth.uri.as_deref().unwrap_or(&original_uri),
+            &info,
+        )?;
+        let result = tokio::time::timeout(
+            auth.timeout,
+            forward_auth::check(&self.auth_connector, &peer, req, auth),
+        )
+        .await;
+        match result {
+            Ok(Ok(AuthResult::Allow(headers))) => {
+                ctx.auth_headers.extend(headers);
+                Ok(false)
+            }
+            Ok(Ok(AuthResult::Deny(mut resp, body))) => {
+                info!(
+                    "[{}.{}]: Denied by auth service with {}",
+                    self.port,
+                    ctx.source.as_deref().unwrap_or_default(),
+                    resp.status.as_str()
+                );
+                self.insert_server_headers(&mut resp, ctx)?;
+                resp.insert_header(header::CONTENT_LENGTH, body.len().to_string())?;
+                session.write_response_header(resp, false).await?;
+                session.write_response_body(Some(body.into()), true).await?;
+                Ok(true)
+            }
+            Ok(Ok(AuthResult::Unexpected(status))) => {
+                error!(
+                    "[{}]: Auth service {:?} answered {}",
+                    self.port,
+                    auth.endpoint,
+                    status.as_str()
+                );
+                self.error_page(session, ctx, StatusCode::INTERNAL_SERVER_ERROR)
+                    .await
+            }
+            Ok(Err(e)) => {
+                error!(
+                    "[{}]: Failed to request auth service {:?}: {}",
+                    self.port, auth.endpoint, e
+                );
+                self.error_page(session, ctx, StatusCode::BAD_GATEWAY).await
+            }
+            Err(_) => {
+                error!(
+                    "[{}]: Timeout requesting auth service {:?}",
+                    self.port, auth.endpoint
+                );
+                self.error_page(session, ctx, StatusCode::BAD_GATEWAY).await
+            }
+        }
+    }
+
+    /// Hold a slot of the concurrency limit, answer 503 if none is available in time.
+    async fn limit_concurrency(
+        &self,
+        session: &mut Session,
+        ctx: &mut GatewayCTX,
+        limit: &Concurrency,
+        source: Option<&str>,
+    ) -> pingora::Result<bool> {
+        match limit
+            .limiter
+            .acquire(ctx.client_ip, limit.queue_timeout)
+            .await
+        {
+            Ok(permits) => {
+                ctx.permits.extend(permits);
+                Ok(false)
+            }
+            Err(reached) => {
+                let value = if reached == "per_ip" {
+                    limit.per_ip
+                } else {
+                    limit.max
+                };
+                let scope = match source {
+                    Some(source) => format!("{}.{}", self.port, source),
+                    None => self.port.to_string(),
+                };
+                info!(
+                    "[{}]: Concurrency limit {} = {} reached by {}",
+                    scope,
+                    reached,
+                    value.unwrap_or_default(),
+                    client_ip(ctx)
+                );
+                metrics::rejected(self.port, source.unwrap_or_default(), reached);
+                self.error_page(session, ctx, StatusCode::SERVICE_UNAVAILABLE)
+                    .await
+            }
+        }
+    }
+
+    /// Apply the timeouts against slow clients to the downstream session.
+    fn apply_timeouts(&self, session: &mut Session, timeouts: &ClientTimeouts) {
+        if let Some(timeout) = timeouts.body {
+            session.set_read_timeout(Some(timeout));
+        }
+        if let Some(timeout) = timeouts.send {
+            session.set_write_timeout(Some(timeout));
+        }
+        // leave the connections pingora is closing alone
+        if session.get_keepalive().is_none() {
+            return;
+        }
+        match timeouts.keepalive {
+            Some(timeout) => session.set_keepalive(keepalive_secs(timeout)),
+            // the listener set the header timeout as keepalive of the first request
+            None if timeouts.header.is_some() => session.set_keepalive(Some(DEFAULT_KEEPALIVE)),
+            None => {}
         }
     }
 
+    /// Prefix the upstream connection with a PROXY header carrying the downstream addresses.
+    fn send_proxy_protocol(
+        &self,
+        peer: &mut HttpPeer,
+        version: ProxyProtocolVersion,
+        session: &Session,
+        ctx: &GatewayCTX,
+    ) {
+        let client = session.client_addr().and_then(|addr| addr.as_inet());
+        let source = ctx.client_ip.map(|ip| {
+            // the port is unknown if the address comes from `real_ip_header`
+            let port = client
+                .filter(|client| client.ip().to_canonical() == ip)
+                .map(|client| client.port())
+                .unwrap_or_default();
+            SocketAddr::new(ip, port)
+        });
+        let destination = session
+            .server_addr()
+            .and_then(|addr| addr.as_inet())
+            .copied();
+        let header = proxy_protocol::encode(version, source, destination);
+
+        // only reuse connections sent with the same header
+        let mut hasher = DefaultHasher::new();
+        header.hash(&mut hasher);
+        peer.group_key = hasher.finish();
+        peer.options.custom_l4 = Some(Arc::new(ProxyProtocolConnect {
+            header,
+            timeout: peer.options.connection_timeout,
+        }));
+    }
+
     fn peer(&self, source: &Proxy) -> Box<HttpPeer> {
         let addr = (source.ip.as_str(), source.port);
 
-        let domain = match &source.host {
-            Some(domain) => domain.clone(),
-            None => source.ip.clone(),
+        let domain = match &source.upstream_tls {
+            Some(UpstreamTls { sni: Some(sni), .. }) => sni.clone(),
+            _ => match &source.host {
+                Some(domain) => domain.clone(),
+                None => source.ip.clone(),
+            },
         };
 
         let mut addrs_iter = addr.to_socket_addrs().unwrap();
@@ -50,51 +493,144 @@ impl Gateway {
 
         peer.options.connection_timeout = Some(Duration::new(3, 0));
 
+        if let Some(tls) = &source.upstream_tls {
+            peer.options.verify_cert = tls.verify_cert;
+            peer.options.verify_hostname = tls.verify_hostname;
+            peer.options.alternative_cn = tls.alternative_cn.clone();
+            peer.options.ca = tls.ca.clone();
+            peer.client_cert_key = tls.client_cert_key.clone();
+        }
+
         Box::new(peer)
     }
 }
 
 pub struct GatewayCTX {
     pub sni: Option<String>,
+    pub sni_captures: Vec<(String, String)>,
     pub source: Option<String>,
+    pub request_uri: Option<String>,
+    pub redirect: Option<StatusCode>,
+    pub client_ip: Option<IpAddr>,
+    pub remote_user: Option<String>,
+    pub auth_headers: Vec<(HeaderName, Vec<HeaderValue>)>,
+    pub jwt_claims: Option<jwt::Claims>,
+    pub permits: Vec<OwnedSemaphorePermit>,
+    pub in_flight: Option<InFlight>,
+    pub body_bytes: usize,
+    pub cors: Option<Vec<(HeaderName, String)>>,
+    /// limit, remaining and reset of the `RateLimit-*` headers, of the tightest limit
+    pub rate_limit: Option<(u32, u32, u64)>,
+    /// body held until `body_limit` of the waf is reached
+    pub waf_body: Option<Vec<u8>>,
+    pub waf_tags: Vec<String>,
+    pub waf_denied: bool,
+    pub captures: Vec<(String, String)>,
+    pub request_id: String,
+    pub upstream_addr: Option<String>,
 }
 
-#[async_trait]
-impl ProxyHttp for Gateway {
-    type CTX = GatewayCTX;
-    fn new_ctx(&self) -> Self::CTX {
+impl GatewayCTX {
+    pub fn new() -> Self {
         GatewayCTX {
             sni: None,
+            sni_captures: Vec::new(),
             source: None,
+            request_uri: None,
+            redirect: None,
+            client_ip: None,
+            remote_user: None,
+            auth_headers: Vec::new(),
+            jwt_claims: None,
+            permits: Vec::new(),
+            in_flight: None,
+            body_bytes: 0,
+            cors: None,
+            rate_limit: None,
+            waf_body: None,
+            waf_tags: Vec::new(),
+            waf_denied: false,
+            captures: Vec::new(),
+            request_id: format!("{:032x}", rand::random::<u128>()),
+            upstream_addr: None,
         }
     }
+}
+
+fn client_ip(ctx: &GatewayCTX) -> String {
+    match ctx.client_ip {
+        Some(ip) => ip.to_string(),
+        None => String::from("-"),
+    }
+}
+
+#[async_trait]
+impl ProxyHttp for Gateway {
+    type CTX = GatewayCTX;
+    fn new_ctx(&self) -> Self::CTX {
+        GatewayCTX::new()
+    }
 
     async fn upstream_peer(
         &self,
         session: &mut Session,
         ctx: &mut Self::CTX,
     ) -> pingora::Result<Box<HttpPeer>> {
-        let header: &mut RequestHeader = session.req_header_mut();
         if let Some(sni) = &ctx.sni {
             if let Some(s) = &ctx.source {
                 let source: &Source = self.routes.get(sni).unwrap().get(s).unwrap();
                 let source = match source {
                     Source::Proxy(proxy) => proxy,
-                    Source::Static(_) => Err(Error::new(HTTPStatus(502)))?,
+                    Source::Static(_) | Source::Return(_) => Err(Error::new(HTTPStatus(502)))?,
+                };
+
+                let mut peer = self.peer(source);
+                if let Some(version) = source.send_proxy_protocol {
+                    self.send_proxy_protocol(&mut peer, version, session, ctx);
+                }
+                debug!("[{}]: Upstream peer: {:?}", self.port, peer);
+                ctx.upstream_addr = Some(peer._address.to_string());
+
+                let heads = source
+                    .headers_request
+                    .as_ref()
+                    .map(|heads| template::render_headers(heads, session, ctx));
+                let host = template::host(session);
+                let forwarded = ForwardedInfo {
+                    peer_ip: ip::peer_ip(session),
+                    client_ip: ctx.client_ip,
+                    proto: template::scheme(session),
+                    host: &host,
+                    port: self.port,
                 };
 
+                let header: &mut RequestHeader = session.req_header_mut();
+                if let Some(conf) = &source.forwarded_headers {
+                    forwarded::apply(header, conf, &forwarded)?;
+                }
                 if let Some(domain) = &source.host {
                     header.insert_header("Host", domain)?;
                 };
-
-                if let Some(heads) = &source.headers_request {
-                    for head in heads {
-                        header.insert_header(String::from(head.0), head.1)?;
+                if source
+                    .options
+                    .auth_basic
+                    .as_ref()
+                    .is_some_and(|auth| auth.strip_authorization)
+                {
+                    header.remove_header(&header::AUTHORIZATION);
+                }
+                for (name, values) in &ctx.auth_headers {
+                    header.remove_header(name);
+                    for value in values {
+                        header.append_header(name.clone(), value.clone())?;
                     }
                 }
 
-                let peer = self.peer(source);
-                debug!("[{}]: Upstream peer: {:?}", self.port, peer);
+                headers::apply_request(
+                    header,
+                    &source.headers_request_remove,
+                    heads.unwrap_or_default(),
+                )?;
 
                 return Ok(peer);
             }
@@ -108,19 +644,49 @@ impl ProxyHttp for Gateway {
         session: &mut Session,
         ctx: &mut Self::CTX,
     ) -> pingora::Result<bool> {
+        self.apply_timeouts(session, &self.timeouts);
         let sni = match session.downstream_session.get_header("Host") {
             None => String::from(""),
             Some(host) => String::from(host.to_str().unwrap()),
         };
+        ctx.client_ip = match &self.real_ip {
+            Some(real_ip) => ip::real_ip(session, real_ip),
+            None => ip::peer_ip(session),
+        };
+        if let Some(limit) = &self.concurrency {
+            if self.limit_concurrency(session, ctx, limit, None).await? {
+                return Ok(true);
+            }
+        }
         let header: &mut RequestHeader = session.req_header_mut();
 
-        let uri = encode_ignore_slash(&header.uri.to_string()).into_owned();
+        let uri = decode_path(&header.uri);
+        let uri_decoded = uri.clone();
         let uri_raw = String::from(&header.uri.to_string());
 
+        if let Some(redirect) = &self.redirect_https {
+            let location = redirect.location(&sni, &uri_raw);
+            info!(
+                "[{}]: {} {} \"{}\" \"{}\" redirect to \"{}\"",
+                self.port,
+                client_ip(ctx),
+                header.method,
+                sni,
+                uri_raw,
+                location
+            );
+            let mut resp = ResponseHeader::build(redirect.status, Some(4))?;
+            self.insert_server_headers(&mut resp, ctx)?;
+            resp.insert_header(header::LOCATION, location)?;
+            resp.insert_header(header::CONTENT_LENGTH, "0")?;
+            session.write_response_header(Box::new(resp), true).await?;
+            return Ok(true);
+        }
+
         let (source, uri) = {
             if self.check_status {
                 let mut re: ((&String, &Source), String) =
-                    find_route(&sni, &uri, &self.routes, 0, ctx).inspect_err(|_| {
+                    find_route(&sni, &uri, header, &self.routes, 0, ctx).inspect_err(|_| {
                         error!("[{}]: Failed to find route {}", self.port, &uri_raw);
                     })?;
 
@@ -128,27 +694,32 @@ impl ProxyHttp for Gateway {
                     if check_status(re.0 .1, re.1.as_str()) {
                         break;
                     }
-                    for fallback in re.0 .1.fallback_as_ref() {
+                    for fallback in &re.0 .1.options().fallback {
+                        let fallback_source = match ctx
+                            .sni
+                            .as_ref()
+                            .and_then(|sni| self.routes.get(sni))
+                            .and_then(|sources| sources.get(fallback))
+                        {
+                            Some(source) => source,
+                            None => {
+                                error!(
+                                    "[{}]: Failed to find fallback source {}",
+                                    self.port, fallback
+                                );
+                                return self
+                                    .error_page(session, ctx, StatusCode::BAD_GATEWAY)
+                                    .await;
+                            }
+                        };
                         re = find_route_with_start(
                             &sni,
                             &re.1,
+                            header,
                             &self.routes,
                             0,
                             ctx,
-                            (
-                                fallback,
-                                match self.routes.get(&sni).unwrap().get(fallback) {
-                                    Some(source) => source,
-                                    None => {
-                                        error!(
-                                            "[{}]: Failed to find fallback source {}",
-                                            self.port, fallback
-                                        );
-                                        return make_page50x(session, StatusCode::BAD_GATEWAY)
-                                            .await;
-                                    }
-                                },
-                            ),
+                            (fallback, fallback_source),
                         )?;
                         if check_status(re.0 .1, re.1.as_str()) {
                             break;
@@ -157,16 +728,19 @@ impl ProxyHttp for Gateway {
                 }
                 re
             } else {
-                find_route(&sni, &uri, &self.routes, 0, ctx)?
+                find_route(&sni, &uri, header, &self.routes, 0, ctx)?
             }
         };
 
         ctx.source = Some(String::from(source.0));
+        ctx.in_flight = Some(InFlight::new(self.port, source.0));
+        ctx.request_uri = Some(uri_raw.clone());
 
         info!(
-            "[{}.{}]: {} \"{}\" \"{}\" \"{}\"",
+            "[{}.{}]: {} {} \"{}\" \"{}\" \"{}\"",
             self.port,
             source.0,
+            client_ip(ctx),
             header.method,
             sni,
             uri_raw,
@@ -178,23 +752,267 @@ impl ProxyHttp for Gateway {
             }
         );
 
-        header.set_uri(
-            match match decode(&uri) {
-                Ok(uri) => uri,
+        if let Some(access) = self
+            .source(ctx)
+            .and_then(|source| source.options().access.as_ref())
+        {
+            if !access.is_allowed(ctx.client_ip) {
+                info!(
+                    "[{}.{}]: Access denied for {}",
+                    self.port,
+                    source.0,
+                    client_ip(ctx)
+                );
+                return self.error_page(session, ctx, StatusCode::FORBIDDEN).await;
+            }
+        }
+
+        if let Source::Proxy(Proxy {
+            request_limits: Some(limits),
+            ..
+        }) = source.1
+        {
+            if limits.headers_exceeded(header) {
+                info!(
+                    "[{}.{}]: Request headers too large from {}",
+                    self.port,
+                    source.0,
+                    client_ip(ctx)
+                );
+                return self
+                    .error_page(session, ctx, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+                    .await;
+            }
+            let length = header
+                .headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+            if length.is_some_and(|length| limits.body_exceeded(length)) {
+                info!(
+                    "[{}.{}]: Request body too large from {}",
+                    self.port,
+                    source.0,
+                    client_ip(ctx)
+                );
+                return self
+                    .error_page(session, ctx, StatusCode::PAYLOAD_TOO_LARGE)
+                    .await;
+            }
+        }
+
+        if let Some(conf) = self.waf(ctx) {
+            let rules = conf.rules.rules();
+            if let Some(status) =
+                self.check_waf(ctx, &rules, |target| waf::header_target(header, target))
+            {
+                return self.error_page(session, ctx, status).await;
+            }
+            // static files and returns do not read the body
+            if matches!(source.1, Source::Proxy(_)) && waf::inspects_body(&rules) {
+                ctx.waf_body = Some(Vec::new());
+            }
+        }
+
+        if let Some(conf) = self
+            .source(ctx)
+            .and_then(|source| source.options().cors.as_ref())
+        {
+            if cors::is_preflight(header) {
+                let headers = match cors::preflight(header, conf) {
+                    Some(headers) => headers,
+                    None => {
+                        info!(
+                            "[{}.{}]: CORS preflight rejected from {}",
+                            self.port,
+                            source.0,
+                            client_ip(ctx)
+                        );
+                        return self.error_page(session, ctx, StatusCode::FORBIDDEN).await;
+                    }
+                };
+                let mut resp = ResponseHeader::build(StatusCode::NO_CONTENT, Some(8))?;
+                self.insert_server_headers(&mut resp, ctx)?;
+                for (name, value) in headers {
+                    resp.insert_header(name, value)?;
+                }
+                resp.insert_header(header::CONTENT_LENGTH, "0")?;
+                session.write_response_header(Box::new(resp), true).await?;
+                return Ok(true);
+            }
+            ctx.cors = Some(cors::response_headers(header, conf));
+        }
+
+        if let Some(auth) = self
+            .source(ctx)
+            .and_then(|source| source.options().auth_basic.as_ref())
+        {
+            match self.auth_basic(header, auth).await {
+                Some(user) => ctx.remote_user = Some(user),
+                None => {
+                    info!(
+                        "[{}.{}]: Unauthorized request from {}",
+                        self.port,
+                        source.0,
+                        client_ip(ctx)
+                    );
+                    return self
+                        .error_page_with_headers(
+                            session,
+                            ctx,
+                            StatusCode::UNAUTHORIZED,
+                            vec![(header::WWW_AUTHENTICATE, auth.challenge())],
+                        )
+                        .await;
+                }
+            }
+        }
+
+        if let Some(conf) = self
+            .source(ctx)
+            .and_then(|source| source.options().jwt.as_ref())
+        {
+            let (result, challenge) = match jwt::token(header, conf) {
+                Some(token) => (
+                    jwt::validate(&token, conf),
+                    "Bearer error=\"invalid_token\"",
+                ),
+                None => (Err(String::from("missing token")), "Bearer"),
+            };
+            match result {
+                Ok(claims) => {
+                    ctx.auth_headers.extend(jwt::claims_headers(&claims, conf));
+                    ctx.jwt_claims = Some(claims);
+                }
                 Err(e) => {
-                    error!(
-                        "[{}.{}]: Failed to parse rewritten uri: {}, {}",
+                    info!(
+                        "[{}.{}]: Invalid token from {}: {}",
                         self.port,
                         source.0,
-                        &uri,
-                        e.to_string()
+                        client_ip(ctx),
+                        e
                     );
-                    return make_page50x(session, StatusCode::BAD_GATEWAY).await;
+                    return self
+                        .error_page_with_headers(
+                            session,
+                            ctx,
+                            StatusCode::UNAUTHORIZED,
+                            vec![(header::WWW_AUTHENTICATE, String::from(challenge))],
+                        )
+                        .await;
                 }
             }
-            .into_owned()
-            .parse::<Uri>()
+        }
+
+        let mut limited: Option<(&RateLimit, f64)> = None;
+        for limit in self
+            .source(ctx)
+            .map(|source| source.options().rate_limit.as_slice())
+            .unwrap_or_default()
+        {
+            let key = match &limit.key {
+                RateLimitKey::ClientIp => ctx.client_ip.map(|ip| ip.to_string()),
+                RateLimitKey::Header(name) => header
+                    .headers
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from),
+                RateLimitKey::Claim(name) => ctx
+                    .jwt_claims
+                    .as_ref()
+                    .and_then(|claims| claims.get(name))
+                    .map(|claim| jwt::claim_values(claim).join(",")),
+                RateLimitKey::Route => Some(String::new()),
+            };
+            // requests without the key are not limited
+            let key = match key {
+                Some(key) => key,
+                None => continue,
+            };
+            let wait = match limit.buckets.acquire(&key, limit.rate, limit.burst) {
+                Ok((remaining, reset)) => {
+                    if !limit.dry_run && ctx.rate_limit.is_none_or(|(_, r, _)| remaining < r) {
+                        ctx.rate_limit =
+                            Some((limit.burst, remaining, (reset.ceil() as u64).max(1)));
+                    }
+                    continue;
+                }
+                Err(wait) => wait,
+            };
+            info!(
+                "[{}.{}]: Rate limit {:?} exceeded by {}{}",
+                self.port,
+                source.0,
+                limit.key,
+                client_ip(ctx),
+                if limit.dry_run { " (dry run)" } else { "" }
+            );
+            if !limit.dry_run && limited.is_none_or(|(_, w)| w < wait) {
+                limited = Some((limit, wait));
+            }
+        }
+        if let Some((limit, wait)) = limited {
+            let wait = (wait.ceil() as u64).max(1);
+            ctx.rate_limit = Some((limit.burst, 0, wait));
+            return self
+                .error_page_with_headers(
+                    session,
+                    ctx,
+                    StatusCode::TOO_MANY_REQUESTS,
+                    vec![(header::RETRY_AFTER, wait.to_string())],
+                )
+                .await;
+        }
+
+        if let Some(auth) = self
+            .source(ctx)
+            .and_then(|source| source.options().forward_auth.as_ref())
+        {
+            if self.forward_auth(session, ctx, auth).await? {
+                return Ok(true);
+            }
+        }
+        self.apply_timeouts(session, &self.timeouts.merge(&source.1.options().timeouts));
+        if let Source::Proxy(Proxy {
+            concurrency: Some(limit),
+            ..
+        }) = source.1
+        {
+            if self
+                .limit_concurrency(session, ctx, limit, Some(source.0))
+                .await?
             {
+                return Ok(true);
+            }
+        }
+        let header: &mut RequestHeader = session.req_header_mut();
+
+        if let Some(status) = ctx.redirect {
+            let location = match encode_uri(&uri).parse::<Uri>() {
+                Ok(location) => location,
+                Err(e) => {
+                    error!(
+                        "[{}.{}]: Failed to parse rewritten uri: {}, {}",
+                        self.port, source.0, &uri, e
+                    );
+                    return self.error_page(session, ctx, StatusCode::BAD_GATEWAY).await;
+                }
+            };
+            let location = if location.scheme().is_some() {
+                location.to_string()
+            } else {
+                format!("{}://{}{}", template::scheme(session), sni, location)
+            };
+            let mut resp = ResponseHeader::build(status, Some(4))?;
+            self.insert_server_headers(&mut resp, ctx)?;
+            resp.insert_header(header::LOCATION, location)?;
+            resp.insert_header(header::CONTENT_LENGTH, "0")?;
+            session.write_response_header(Box::new(resp), true).await?;
+            return Ok(true);
+        }
+
+        // an untouched uri is sent as received, `%2F` and so are not decoded
+        if uri != uri_decoded {
+            header.set_uri(match encode_uri(&uri).parse::<Uri>() {
                 Ok(uri) => uri,
                 Err(e) => {
                     error!(
@@ -204,14 +1022,35 @@ impl ProxyHttp for Gateway {
                         &uri,
                         e.to_string()
                     );
-                    return make_page50x(session, StatusCode::BAD_GATEWAY).await;
+                    return self.error_page(session, ctx, StatusCode::BAD_GATEWAY).await;
                 }
-            },
-        );
+            });
+        }
 
         match source.1 {
             Source::Proxy(_) => {}
             Source::Static(source) => {
+                if let Some(link) = &source.secure_link {
+                    let request_uri = ctx.request_uri.as_deref().unwrap_or_default();
+                    let status = match secure_link::verify(link, request_uri, ctx.client_ip) {
+                        LinkStatus::Valid => None,
+                        LinkStatus::Invalid => Some(StatusCode::FORBIDDEN),
+                        LinkStatus::Expired => Some(StatusCode::GONE),
+                    };
+                    if let Some(status) = status {
+                        info!(
+                            "[{}.{}]: Rejected link from {} with {}",
+                            self.port,
+                            ctx.source.as_deref().unwrap_or_default(),
+                            client_ip(ctx),
+                            status.as_str()
+                        );
+                        return self.error_page(session, ctx, status).await;
+                    }
+                }
+                if path::has_parent(uri.split('?').next().unwrap_or_default()) {
+                    return self.error_page(session, ctx, StatusCode::BAD_REQUEST).await;
+                }
                 let mut status = StatusCode::OK;
                 let mut file_path = path::resolve_uri(&source.root, uri.as_str());
                 file_path = file_path.split('?').collect::<Vec<&str>>()[0].to_string();
@@ -224,47 +1063,156 @@ impl ProxyHttp for Gateway {
                         error!("File not exist: \"{}\"", &file_path);
                         file_path = path::resolve_uri(&source.root, "/404.html");
                         status = StatusCode::NOT_FOUND;
-                        std::fs::read(&file_path).unwrap_or_else(|_| Vec::from(PAGE404))
+                        match std::fs::read(&file_path) {
+                            // a configured error page takes precedence over `404.html`
+                            Ok(file) if source.options.error_page.get(status).is_none() => file,
+                            _ => return self.error_page(session, ctx, status).await,
+                        }
                     }
                 };
 
                 let content_length = file.len();
+                let heads = source
+                    .options
+                    .headers_response
+                    .as_ref()
+                    .map(|heads| template::render_headers(heads, session, ctx));
 
                 let mut resp = ResponseHeader::build(status, Some(4))?;
-                resp.insert_header(header::SERVER, "Pingpong")?;
+                self.insert_server_headers(&mut resp, ctx)?;
                 resp.insert_header(header::CONTENT_LENGTH, content_length.to_string())?;
                 resp.insert_header(header::CONTENT_TYPE, get_mime_type(&file_path))?;
+                headers::apply_response(
+                    &mut resp,
+                    &source.options.headers_response_remove,
+                    heads.unwrap_or_default(),
+                )?;
                 session.write_response_header(Box::new(resp), false).await?;
 
                 session.write_response_body(Some(file.into()), true).await?;
                 return Ok(true);
             }
+            Source::Return(ret) => {
+                let render = |template: &str| {
+                    template::render(template, |name| {
+                        template::request_variable(session, ctx, name)
+                    })
+                };
+                let redirect = ret.redirect.as_deref().map(render);
+                let body = ret.body.as_deref().map(render).unwrap_or_default();
+                let heads = ret
+                    .options
+                    .headers_response
+                    .as_ref()
+                    .map(|heads| template::render_headers(heads, session, ctx));
+
+                let mut resp = ResponseHeader::build(ret.status, Some(4))?;
+                self.insert_server_headers(&mut resp, ctx)?;
+                if let Some(redirect) = redirect {
+                    resp.insert_header(header::LOCATION, redirect)?;
+                }
+                if !body.is_empty() {
+                    resp.insert_header(header::CONTENT_TYPE, &ret.content_type)?;
+                }
+                resp.insert_header(header::CONTENT_LENGTH, body.len().to_string())?;
+                headers::apply_response(
+                    &mut resp,
+                    &ret.options.headers_response_remove,
+                    heads.unwrap_or_default(),
+                )?;
+                session
+                    .write_response_header(Box::new(resp), body.is_empty())
+                    .await?;
+                if !body.is_empty() {
+                    session.write_response_body(Some(body.into()), true).await?;
+                }
+                return Ok(true);
+            }
         }
 
         Ok(false)
     }
 
+    async fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora::Result<()> {
+        let limits = match self.source(ctx) {
+            Some(Source::Proxy(Proxy {
+                request_limits: Some(limits),
+                ..
+            })) => Some(limits),
+            _ => None,
+        };
+        if let Some(chunk) = body {
+            ctx.body_bytes += chunk.len();
+        }
+        // bodies without `Content-Length` are only known to be too large here
+        if limits.is_some_and(|limits| limits.body_exceeded(ctx.body_bytes)) {
+            info!(
+                "[{}.{}]: Request body too large from {}",
+                self.port,
+                ctx.source.as_deref().unwrap_or_default(),
+                client_ip(ctx)
+            );
+            return Error::e_explain(HTTPStatus(413), "request body too large");
+        }
+        let conf = self.waf(ctx);
+        if let (Some(held), Some(conf)) = (&mut ctx.waf_body, conf) {
+            if let Some(chunk) = body.take() {
+                held.extend_from_slice(&chunk);
+            }
+            if held.len() < conf.body_limit && !end_of_stream {
+                *body = Some(Bytes::new());
+                return Ok(());
+            }
+            let held = ctx.waf_body.take().unwrap_or_default();
+            let text = waf::body_target(
+                session.req_header(),
+                &held[..held.len().min(conf.body_limit)],
+            );
+            let rules = conf.rules.rules();
+            let denied = self.check_waf(ctx, &rules, |target| match target {
+                WafTarget::Body => vec![Cow::Borrowed(text.as_str())],
+                _ => Vec::new(),
+            });
+            if let Some(status) = denied {
+                ctx.waf_denied = true;
+                return Error::e_explain(HTTPStatus(status.as_u16()), "request denied by waf");
+            }
+            *body = Some(Bytes::from(held));
+        }
+        Ok(())
+    }
+
     async fn response_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) -> pingora::Result<()>
     where
         Self::CTX: Send + Sync,
     {
-        // replace any existing header
-        upstream_response.insert_header("Server", "Pingpong")?;
+        self.insert_server_headers(upstream_response, ctx)?;
 
         if let Some(sni) = &ctx.sni {
             if let Some(s) = &ctx.source {
                 let source: &Source = self.routes.get(sni).unwrap().get(s).unwrap();
 
-                if let Some(heads) = &source.headers_response_as_ref() {
-                    for head in heads {
-                        upstream_response.insert_header(String::from(head.0), head.1)?;
-                    }
-                }
+                let heads = source
+                    .options()
+                    .headers_response
+                    .as_ref()
+                    .map(|heads| template::render_headers(heads, session, ctx));
+                headers::apply_response(
+                    upstream_response,
+                    &source.options().headers_response_remove,
+                    heads.unwrap_or_default(),
+                )?;
             }
         }
 
@@ -274,18 +1222,27 @@ impl ProxyHttp for Gateway {
     async fn fail_to_proxy(
         &self,
         session: &mut Session,
-        _e: &Error,
-        _ctx: &mut Self::CTX,
+        e: &Error,
+        ctx: &mut Self::CTX,
     ) -> FailToProxy
     where
         Self::CTX: Send + Sync,
     {
-        make_page50x(session, StatusCode::BAD_GATEWAY)
-            .await
-            .unwrap();
+        let status = match (e.etype(), e.esource()) {
+            (HTTPStatus(code), _) => StatusCode::from_u16(*code).unwrap_or(StatusCode::BAD_GATEWAY),
+            (ReadTimedout, ErrorSource::Downstream) => StatusCode::REQUEST_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        // the client may be too slow to take the page in `send_timeout`
+        if let Err(e) = self.error_page(session, ctx, status).await {
+            debug!("[{}]: Failed to send error page: {}", self.port, e);
+        }
         FailToProxy {
-            error_code: 502,
-            can_reuse_downstream: true,
+            error_code: status.as_u16(),
+            // the rest of the body is left unread
+            can_reuse_downstream: status != StatusCode::PAYLOAD_TOO_LARGE
+                && status != StatusCode::REQUEST_TIMEOUT
+                && !ctx.waf_denied,
         }
     }
 }
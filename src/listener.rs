@@ -0,0 +1,601 @@
+use crate::config::{keepalive_secs, ProxyProtocol};
+use crate::util::proxy_protocol::{self, Parsed};
+use async_trait::async_trait;
+use log::{debug, error};
+use pingora::apps::{HttpServerApp, ServerApp};
+use pingora::protocols::http::ServerSession;
+use pingora::protocols::l4::socket::SocketAddr;
+use pingora::protocols::raw_connect::ProxyDigest;
+use pingora::protocols::tls::server::handshake;
+use pingora::protocols::tls::{SslDigest, TlsRef};
+use pingora::protocols::{
+    GetProxyDigest, GetSocketDigest, GetTimingDigest, Peek, Shutdown, SocketDigest, Ssl, Stream,
+    TimingDigest, UniqueID, UniqueIDType, ALPN,
+};
+use pingora::server::ShutdownWatch;
+use pingora::tls::ssl::SslAcceptor;
+use std::future::Future;
+use std::io::IoSlice;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+/// Max time to wait for the PROXY header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Max length of a PROXY header, a v2 one may carry extensions.
+const HEADER_MAX_LENGTH: usize = 16 + 65535;
+
+/// Stream after the PROXY header, with the bytes read ahead and the address of the original
+/// client.
+#[derive(Debug)]
+pub struct ProxiedStream {
+    inner: Stream,
+    read_ahead: Vec<u8>,
+    socket_digest: Option<Arc<SocketDigest>>,
+}
+
+impl ProxiedStream {
+    fn new(inner: Stream, read_ahead: Vec<u8>, client: Option<std::net::SocketAddr>) -> Self {
+        let socket_digest = client.and_then(|client| {
+            // the digest of the inner stream has cached the address of the proxy
+            let fd = inner
+                .as_any()
+                .downcast_ref::<pingora::protocols::l4::stream::Stream>()?
+                .as_raw_fd();
+            let digest = SocketDigest::from_raw_fd(fd);
+            let _ = digest.peer_addr.set(Some(SocketAddr::Inet(client)));
+            Some(Arc::new(digest))
+        });
+        Self {
+            inner,
+            read_ahead,
+            socket_digest,
+        }
+    }
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if !self.read_ahead.is_empty() {
+            let n = self.read_ahead.len().min(buf.remaining());
+            buf.put_slice(&self.read_ahead[..n]);
+            self.read_ahead.drain(..n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[async_trait]
+impl Shutdown for ProxiedStream {
+    async fn shutdown(&mut self) {
+        self.inner.shutdown().await
+    }
+}
+
+impl UniqueID for ProxiedStream {
+    fn id(&self) -> UniqueIDType {
+        self.inner.id()
+    }
+}
+
+impl Ssl for ProxiedStream {}
+
+impl GetTimingDigest for ProxiedStream {
+    fn get_timing_digest(&self) -> Vec<Option<TimingDigest>> {
+        self.inner.get_timing_digest()
+    }
+}
+
+impl GetProxyDigest for ProxiedStream {
+    fn get_proxy_digest(&self) -> Option<Arc<ProxyDigest>> {
+        self.inner.get_proxy_digest()
+    }
+}
+
+impl GetSocketDigest for ProxiedStream {
+    fn get_socket_digest(&self) -> Option<Arc<SocketDigest>> {
+        match &self.socket_digest {
+            Some(digest) => Some(digest.clone()),
+            None => self.inner.get_socket_digest(),
+        }
+    }
+}
+
+impl Peek for ProxiedStream {}
+
+/// HTTP/1 stream failing reads once a request header takes longer than `timeout` in total,
+/// counted from the connection for the first request and from the first byte for later ones.
+#[derive(Debug)]
+pub struct HeaderDeadline {
+    inner: Stream,
+    timeout: Duration,
+    /// set before each request, cleared when the end of its header is read
+    waiting: AtomicBool,
+    deadline: Option<Pin<Box<Sleep>>>,
+    /// last bytes read, to find the end of the header across reads
+    tail: Vec<u8>,
+}
+
+impl HeaderDeadline {
+    fn new(inner: Stream, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            waiting: AtomicBool::new(true),
+            deadline: Some(Box::pin(tokio::time::sleep(timeout))),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Wait for the header of the next request.
+    fn arm(&self) {
+        self.waiting.store(true, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for HeaderDeadline {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if !*self.waiting.get_mut() {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        }
+        if let Some(deadline) = &mut self.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "client header timeout",
+                )));
+            }
+        }
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = &buf.filled()[filled..];
+        if !read.is_empty() {
+            if self.deadline.is_none() {
+                // the idle time before is left to the keepalive timeout
+                self.deadline = Some(Box::pin(tokio::time::sleep(self.timeout)));
+            }
+            let mut bytes = std::mem::take(&mut self.tail);
+            bytes.extend_from_slice(read);
+            if bytes.windows(4).any(|w| w == b"\r\n\r\n") {
+                *self.waiting.get_mut() = false;
+                self.deadline = None;
+            } else {
+                self.tail = bytes.split_off(bytes.len().saturating_sub(3));
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for HeaderDeadline {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[async_trait]
+impl Shutdown for HeaderDeadline {
+    async fn shutdown(&mut self) {
+        self.inner.shutdown().await
+    }
+}
+
+impl UniqueID for HeaderDeadline {
+    fn id(&self) -> UniqueIDType {
+        self.inner.id()
+    }
+}
+
+impl Ssl for HeaderDeadline {
+    fn get_ssl(&self) -> Option<&TlsRef> {
+        self.inner.get_ssl()
+    }
+
+    fn get_ssl_digest(&self) -> Option<Arc<SslDigest>> {
+        self.inner.get_ssl_digest()
+    }
+
+    fn selected_alpn_proto(&self) -> Option<ALPN> {
+        self.inner.selected_alpn_proto()
+    }
+}
+
+impl GetTimingDigest for HeaderDeadline {
+    fn get_timing_digest(&self) -> Vec<Option<TimingDigest>> {
+        self.inner.get_timing_digest()
+    }
+}
+
+impl GetProxyDigest for HeaderDeadline {
+    fn get_proxy_digest(&self) -> Option<Arc<ProxyDigest>> {
+        self.inner.get_proxy_digest()
+    }
+}
+
+impl GetSocketDigest for HeaderDeadline {
+    fn get_socket_digest(&self) -> Option<Arc<SocketDigest>> {
+        self.inner.get_socket_digest()
+    }
+}
+
+impl Peek for HeaderDeadline {}
+
+/// Accept PROXY protocol and optionally TLS in front of the http application, as the listeners
+/// of pingora handshake TLS before any byte can be read.
+pub struct Listener<A> {
+    app: Arc<A>,
+    proxy_protocol: ProxyProtocol,
+    tls: Option<SslAcceptor>,
+    header_timeout: Option<Duration>,
+}
+
+impl<A> Listener<A> {
+    pub fn new(
+        app: A,
+        proxy_protocol: ProxyProtocol,
+        tls: Option<SslAcceptor>,
+        header_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            app: Arc::new(app),
+            proxy_protocol,
+            tls,
+            header_timeout,
+        }
+    }
+
+    async fn read_header(&self, mut stream: Stream) -> Result<ProxiedStream, String> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            match proxy_protocol::parse(&buf)? {
+                Parsed::Incomplete => {}
+                Parsed::NotProxy if matches!(self.proxy_protocol, ProxyProtocol::Optional(_)) => {
+                    return Ok(ProxiedStream::new(stream, buf, None));
+                }
+                Parsed::NotProxy => Err("connection without PROXY header")?,
+                Parsed::Header(header, length) => {
+                    debug!("PROXY header: {:?}", header);
+                    return Ok(ProxiedStream::new(
+                        stream,
+                        buf.split_off(length),
+                        header.source,
+                    ));
+                }
+            }
+            if buf.len() >= HEADER_MAX_LENGTH {
+                Err("PROXY header too long")?;
+            }
+            let n = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+            if n == 0 {
+                Err("connection closed before PROXY header")?;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl<A: HttpServerApp + Send + Sync + 'static> Listener<A> {
+    /// Serve HTTP/1 connections like pingora does, except each request header must be read
+    /// within the header timeout, see `HeaderDeadline`. The wait for the first one is fixed to
+    /// 60s by pingora otherwise, later ones wait for the keepalive timeout before their first byte.
+    async fn process_http(&self, stream: Stream, shutdown: &ShutdownWatch) -> Option<Stream> {
+        let header_timeout = match self.header_timeout {
+            Some(timeout)
+                if stream.selected_alpn_proto() != Some(ALPN::H2)
+                    && !self.app.server_options().is_some_and(|o| o.h2c) =>
+            {
+                timeout
+            }
+            _ => return self.app.process_new(stream, shutdown).await,
+        };
+        let mut session =
+            ServerSession::new_http1(Box::new(HeaderDeadline::new(stream, header_timeout)));
+        if *shutdown.borrow() {
+            session.set_keepalive(None);
+        } else {
+            session.set_keepalive(keepalive_secs(header_timeout));
+        }
+        session.set_keepalive_reuses_remaining(
+            self.app
+                .server_options()
+                .and_then(|o| o.keepalive_request_limit),
+        );
+        let mut result = self.app.process_new_http(session, shutdown).await;
+        while let Some((stream, settings)) = result.map(|r| r.consume()) {
+            // close kept-alive connections once the server is shutting down
+            if *shutdown.borrow() {
+                break;
+            }
+            if let Some(stream) = stream.as_any().downcast_ref::<HeaderDeadline>() {
+                stream.arm();
+            }
+            let mut session = ServerSession::new_http1(stream);
+            if let Some(settings) = settings {
+                settings.apply_to_session(&mut session);
+            }
+            result = self.app.process_new_http(session, shutdown).await;
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl<A: HttpServerApp + Send + Sync + 'static> ServerApp for Listener<A> {
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        if self.proxy_protocol == ProxyProtocol::Off
+            || stream.as_any().is::<ProxiedStream>()
+            || stream
+                .as_any()
+                .is::<pingora::protocols::tls::SslStream<ProxiedStream>>()
+        {
+            // reused connection, the header is already consumed
+            return self.process_http(stream, shutdown).await;
+        }
+        let peer = stream
+            .get_socket_digest()
+            .and_then(|d| d.peer_addr().cloned());
+        let trusted = peer
+            .as_ref()
+            .and_then(|peer| peer.as_inet())
+            .is_some_and(|peer| self.proxy_protocol.trusts(&peer.ip()));
+        if !trusted {
+            if let ProxyProtocol::Required(_) = self.proxy_protocol {
+                error!("PROXY header not allowed from {:?}", peer);
+                return None;
+            }
+        }
+        let read = async {
+            match trusted {
+                true => self.read_header(stream).await,
+                // a PROXY header of an untrusted peer is left to fail as http
+                false => Ok(ProxiedStream::new(stream, Vec::new(), None)),
+            }
+        };
+        let stream = match tokio::time::timeout(HEADER_TIMEOUT, read).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => {
+                error!("Failed to read PROXY header from {:?}: {}", peer, e);
+                return None;
+            }
+            Err(_) => {
+                error!("Timeout reading PROXY header from {:?}", peer);
+                return None;
+            }
+        };
+        let stream: Stream = match &self.tls {
+            None => Box::new(stream),
+            Some(acceptor) => match handshake(acceptor, stream).await {
+                Ok(stream) => Box::new(stream),
+                Err(e) => {
+                    error!("Downstream handshake error from {:?}: {}", peer, e);
+                    return None;
+                }
+            },
+        };
+        self.process_http(stream, shutdown).await
+    }
+
+    async fn cleanup(&self) {
+        self.app.cleanup().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::apps::{HttpPersistentSettings, HttpServerOptions, ReusedHttpStream};
+    use pingora::http::ResponseHeader;
+    use std::time::Instant;
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+    use tokio::sync::watch;
+
+    /// Answers 200 to every request, and starts the shutdown at the first one if asked.
+    struct Ok200 {
+        options: HttpServerOptions,
+        shutdown: Option<watch::Sender<bool>>,
+    }
+
+    #[async_trait]
+    impl HttpServerApp for Ok200 {
+        async fn process_new_http(
+            self: &Arc<Self>,
+            mut session: ServerSession,
+            _shutdown: &ShutdownWatch,
+        ) -> Option<ReusedHttpStream> {
+            if !session.read_request().await.ok()? {
+                return None;
+            }
+            if let Some(shutdown) = &self.shutdown {
+                shutdown.send_replace(true);
+            }
+            let mut resp = ResponseHeader::build(200, None).unwrap();
+            resp.insert_header("Content-Length", "0").unwrap();
+            session.write_response_header(Box::new(resp)).await.ok()?;
+            let settings = HttpPersistentSettings::for_session(&session);
+            let stream = session.finish().await.ok()??;
+            Some(ReusedHttpStream::new(stream, Some(settings)))
+        }
+
+        fn server_options(&self) -> Option<&HttpServerOptions> {
+            Some(&self.options)
+        }
+    }
+
+    /// Serve a connection with a header timeout of 300ms, returns the client side.
+    fn serve(requests: Option<u32>, shutdown_at_first: bool) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(4096);
+        let (sender, receiver) = watch::channel(false);
+        let mut options = HttpServerOptions::default();
+        options.keepalive_request_limit = requests;
+        let app = Ok200 {
+            options,
+            shutdown: shutdown_at_first.then_some(sender.clone()),
+        };
+        let listener = Listener::new(
+            app,
+            ProxyProtocol::Off,
+            None,
+            Some(Duration::from_millis(300)),
+        );
+        tokio::spawn(async move {
+            let _sender = sender;
+            listener.process_http(Box::new(server), &receiver).await;
+        });
+        client
+    }
+
+    /// Status line of the response, `None` once the connection is closed.
+    async fn response(client: &mut DuplexStream) -> Option<String> {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            match client.read(&mut chunk).await {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+        let text = String::from_utf8_lossy(&buf);
+        text.lines().next().map(String::from)
+    }
+
+    async fn request(client: &mut DuplexStream) -> Option<String> {
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+            .await
+            .ok()?;
+        response(client).await
+    }
+
+    /// Send a header line every 100ms, returns the time until the connection is closed.
+    async fn trickle(client: DuplexStream) -> Duration {
+        let (mut read, mut write) = tokio::io::split(client);
+        let start = Instant::now();
+        tokio::spawn(async move {
+            let _ = write.write_all(b"GET / HTTP/1.1\r\n").await;
+            for _ in 0..20 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if write.write_all(b"X-A: b\r\n").await.is_err() {
+                    break;
+                }
+            }
+        });
+        let mut chunk = [0u8; 1024];
+        while let Ok(n) = read.read(&mut chunk).await {
+            if n == 0 {
+                break;
+            }
+        }
+        start.elapsed()
+    }
+
+    #[tokio::test]
+    async fn trickled_header_is_cut_off() {
+        let elapsed = trickle(serve(None, false)).await;
+        assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn trickled_header_of_reused_connection_is_cut_off() {
+        let mut client = serve(None, false);
+        assert_eq!(request(&mut client).await.unwrap(), "HTTP/1.1 200 OK");
+        // waiting for the next request is not counted
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(request(&mut client).await.unwrap(), "HTTP/1.1 200 OK");
+        let elapsed = trickle(client).await;
+        assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn connection_closes_after_keepalive_requests() {
+        // pingora counts the reuses after the first request
+        let mut client = serve(Some(2), false);
+        for _ in 0..3 {
+            assert_eq!(request(&mut client).await.unwrap(), "HTTP/1.1 200 OK");
+        }
+        assert_eq!(request(&mut client).await, None);
+    }
+
+    #[tokio::test]
+    async fn connection_closes_on_shutdown() {
+        let mut client = serve(None, true);
+        assert_eq!(request(&mut client).await.unwrap(), "HTTP/1.1 200 OK");
+        let start = Instant::now();
+        assert_eq!(response(&mut client).await, None);
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+}
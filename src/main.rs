@@ -1,17 +1,25 @@
 mod config;
 mod gateway;
+mod listener;
 mod util;
 
-use crate::config::Importable;
+use crate::config::{Importable, ProxyProtocol, SecureLink, SecureLinkRaw, Ssl};
 use crate::gateway::Gateway;
-use crate::util::path;
+use crate::listener::Listener;
+use crate::util::route::Routes;
+use crate::util::{path, secure_link};
 use anyhow::anyhow;
 use log::debug;
 use pingora::prelude::*;
+use pingora::apps::HttpServerOptions;
+use pingora::proxy::http_proxy;
+use pingora::services::listening::Service;
+use pingora::tls::ssl::{SslAcceptor, SslFiletype, SslMethod};
 use simplelog::*;
 use std::collections::HashMap;
 use std::env;
 use std::fs::OpenOptions;
+use std::net::IpAddr;
 use std::path::Path;
 use std::str::FromStr;
 use structopt::StructOpt;
@@ -53,6 +61,31 @@ struct CommandOpt {
 
     #[structopt(flatten)]
     base_opts: Opt,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Print a signed url for `secure_link` of static sources.
+    #[structopt(name = "sign-url")]
+    SignUrl {
+        /// Url to sign, like `/files/report.pdf`.
+        url: String,
+        #[structopt(long)]
+        secret_file: String,
+        /// Seconds the url stays valid.
+        #[structopt(long, default_value = "3600")]
+        ttl: u64,
+        /// Client address to sign, for `bind_ip`.
+        #[structopt(long)]
+        ip: Option<IpAddr>,
+        #[structopt(long, default_value = "expires")]
+        expires_param: String,
+        #[structopt(long, default_value = "signature")]
+        signature_param: String,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -63,6 +96,28 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if let Some(Command::SignUrl {
+        url,
+        secret_file,
+        ttl,
+        ip,
+        expires_param,
+        signature_param,
+    }) = command_opts.command
+    {
+        let conf = SecureLink::from_raw(
+            SecureLinkRaw {
+                secret_file,
+                bind_ip: Some(ip.is_some()),
+                expires_param: Some(expires_param),
+                signature_param: Some(signature_param),
+            },
+            &format!("{}/", env::current_dir()?.display()),
+        )?;
+        println!("{}", secure_link::signed_url(&conf, &url, ttl, ip));
+        return Ok(());
+    }
+
     let base = env::current_exe()?;
     let base = base.to_str().unwrap();
 
@@ -127,25 +182,48 @@ fn main() -> anyhow::Result<()> {
         debug!("Loading server on port {}", port);
         let mut service_config: HashMap<String, HashMap<String, config::Source>> = HashMap::new();
 
-        for source in i.1.source {
+        for source in &i.1.source {
             debug!("Loading source {}", source.0);
             debug!("Source {}: {:?}", source.0, source.1);
-            let sni = match &source.1.sni_as_ref() {
-                None => "",
-                Some(sni) => sni,
+            let sni = match source.1.options().sni.is_empty() {
+                true => vec![String::new()],
+                false => source.1.options().sni.clone(),
             };
-            if !service_config.contains_key(sni) {
-                service_config.insert(String::from(sni), HashMap::new());
+            for sni in sni {
+                service_config
+                    .entry(sni)
+                    .or_default()
+                    .insert(source.0.clone(), source.1.clone());
             }
-            service_config
-                .get_mut(sni)
-                .unwrap()
-                .insert(source.0.clone(), source.1);
             debug!("Source {} loaded", source.0);
         }
-        let mut service = http_proxy_service(
-            &server.configuration,
-            Gateway::new(port, service_config, i.1.check_status),
+        let gateway = Gateway::new(
+            port,
+            Routes::new(service_config)?,
+            &i.1,
+            config.server_tokens.clone(),
+        );
+        let proxy_protocol = i.1.proxy_protocol != ProxyProtocol::Off;
+        // with PROXY protocol, tls is handshaked after the header
+        let tls = match &i.1.ssl {
+            Some(ssl) if proxy_protocol => Some(tls_acceptor(ssl)?),
+            _ => None,
+        };
+        let mut proxy = http_proxy(&server.configuration, gateway);
+        if let Some(requests) = i.1.timeouts.keepalive_requests {
+            let mut options = HttpServerOptions::default();
+            // pingora counts the reuses after the first request
+            options.keepalive_request_limit = Some(requests - 1);
+            proxy.server_options = Some(options);
+        }
+        let mut service = Service::new(
+            String::from("Pingpong"),
+            Listener::new(
+                proxy,
+                i.1.proxy_protocol.clone(),
+                tls,
+                i.1.timeouts.header,
+            ),
         );
 
         match i.1.threads {
@@ -154,6 +232,10 @@ fn main() -> anyhow::Result<()> {
         };
 
         match i.1.ssl {
+            _ if proxy_protocol => {
+                debug!("proxy protocol enabled");
+                service.add_tcp(&format!("0.0.0.0:{}", port));
+            }
             None => {
                 debug!("ssl disabled");
                 service.add_tcp(&format!("0.0.0.0:{}", port));
@@ -173,7 +255,26 @@ fn main() -> anyhow::Result<()> {
         server.add_service(service);
         debug!("Server on port {} loaded", port);
     }
+
+    if let Some(addr) = &config.metrics {
+        let mut prometheus = Service::prometheus_http_service();
+        prometheus.add_tcp(addr);
+        server.add_service(prometheus);
+        debug!("Metrics on {} loaded", addr);
+    }
     debug!("Pingpong bootstrapped");
 
     server.run_forever()
 }
+
+fn tls_acceptor(ssl: &Ssl) -> anyhow::Result<SslAcceptor> {
+    let err = || anyhow!("Failed to read cert:\n{}\n{}", &ssl.cert, &ssl.key);
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).or(Err(err()))?;
+    acceptor
+        .set_private_key_file(&ssl.key, SslFiletype::PEM)
+        .or(Err(err()))?;
+    acceptor
+        .set_certificate_chain_file(&ssl.cert)
+        .or(Err(err()))?;
+    Ok(acceptor.build())
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Number of client addresses kept, new clients are rejected while none of them is idle.
+const MAX_CLIENTS: usize = 65536;
+
+/// Semaphores of in-flight requests, in total and per client address.
+pub struct Limiter {
+    total: Option<Arc<Semaphore>>,
+    per_ip: Option<usize>,
+    clients: Mutex<HashMap<IpAddr, Arc<Semaphore>>>,
+}
+
+impl Debug for Limiter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Limiter").finish()
+    }
+}
+
+async fn acquire(semaphore: Arc<Semaphore>, timeout: Duration) -> Option<OwnedSemaphorePermit> {
+    if timeout.is_zero() {
+        return semaphore.try_acquire_owned().ok();
+    }
+    match tokio::time::timeout(timeout, semaphore.acquire_owned()).await {
+        Ok(Ok(permit)) => Some(permit),
+        _ => None,
+    }
+}
+
+impl Limiter {
+    pub fn new(max: Option<usize>, per_ip: Option<usize>) -> Self {
+        Self {
+            total: max.map(|max| Arc::new(Semaphore::new(max))),
+            per_ip,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Semaphore of the client, `None` when the map is full of clients with requests in flight.
+    fn client(&self, ip: IpAddr, per_ip: usize) -> Option<Arc<Semaphore>> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&ip) {
+            clients.retain(|_, s| s.available_permits() < per_ip);
+            if clients.len() >= MAX_CLIENTS {
+                return None;
+            }
+        }
+        Some(
+            clients
+                .entry(ip)
+                .or_insert_with(|| Arc::new(Semaphore::new(per_ip)))
+                .clone(),
+        )
+    }
+
+    /// Wait up to `timeout` for a slot of the client, then one in total. Returns the limit
+    /// reached on failure.
+    pub async fn acquire(
+        &self,
+        ip: Option<IpAddr>,
+        timeout: Duration,
+    ) -> Result<Vec<OwnedSemaphorePermit>, &'static str> {
+        let mut permits = Vec::new();
+        if let (Some(per_ip), Some(ip)) = (self.per_ip, ip) {
+            let client = self.client(ip, per_ip).ok_or("per_ip")?;
+            permits.push(acquire(client, timeout).await.ok_or("per_ip")?);
+        }
+        if let Some(total) = &self.total {
+            permits.push(acquire(total.clone(), timeout).await.ok_or("max")?);
+        }
+        Ok(permits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limiter, MAX_CLIENTS};
+    use std::net::{IpAddr, Ipv6Addr};
+    use std::time::Duration;
+
+    fn ip(i: usize) -> Option<IpAddr> {
+        Some(IpAddr::V6(Ipv6Addr::from(i as u128)))
+    }
+
+    #[tokio::test]
+    async fn busy_clients_are_kept() {
+        let limiter = Limiter::new(None, Some(1));
+        let mut permits = Vec::new();
+        for i in 0..MAX_CLIENTS {
+            permits.push(limiter.acquire(ip(i), Duration::ZERO).await.unwrap());
+        }
+        assert_eq!(
+            limiter.acquire(ip(MAX_CLIENTS), Duration::ZERO).await.err(),
+            Some("per_ip")
+        );
+        assert!(limiter.acquire(ip(0), Duration::ZERO).await.is_err());
+
+        // an idle client makes room for a new one
+        permits.pop();
+        assert!(limiter
+            .acquire(ip(MAX_CLIENTS), Duration::ZERO)
+            .await
+            .is_ok());
+        assert_eq!(limiter.clients.lock().unwrap().len(), MAX_CLIENTS);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::config::Cors;
+use http::{header, HeaderName, Method};
+use pingora::http::RequestHeader;
+
+/// Response headers removed from upstream responses, the ones of Pingpong are used instead.
+pub const RESPONSE_HEADERS: [HeaderName; 3] = [
+    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+];
+
+fn origin(req: &RequestHeader) -> Option<&str> {
+    req.headers.get(header::ORIGIN)?.to_str().ok()
+}
+
+pub fn is_preflight(req: &RequestHeader) -> bool {
+    req.method == Method::OPTIONS
+        && req.headers.contains_key(header::ORIGIN)
+        && req
+            .headers
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+fn allow_origin(origin: &str, conf: &Cors) -> Vec<(HeaderName, String)> {
+    let origin = if conf.any_origin { "*" } else { origin };
+    let mut headers = vec![(header::ACCESS_CONTROL_ALLOW_ORIGIN, String::from(origin))];
+    if conf.credentials {
+        headers.push((
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            String::from("true"),
+        ));
+    }
+    headers
+}
+
+/// Headers answering a preflight, `None` if the origin, method or any header is not allowed.
+pub fn preflight(req: &RequestHeader, conf: &Cors) -> Option<Vec<(HeaderName, String)>> {
+    let origin = origin(req).filter(|origin| conf.allows_origin(origin))?;
+    let method = req
+        .headers
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)?
+        .to_str()
+        .ok()?;
+    if !conf.methods.iter().any(|m| m.as_str() == method) {
+        return None;
+    }
+    let requested = req
+        .headers
+        .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    if let Some(allowed) = &conf.headers {
+        if !requested.iter().all(|name| allowed.contains(name)) {
+            return None;
+        }
+    }
+
+    let mut headers = allow_origin(origin, conf);
+    headers.push((
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        conf.methods
+            .iter()
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+    ));
+    if !requested.is_empty() {
+        headers.push((header::ACCESS_CONTROL_ALLOW_HEADERS, requested.join(", ")));
+    }
+    if let Some(max_age) = conf.max_age {
+        headers.push((header::ACCESS_CONTROL_MAX_AGE, max_age.to_string()));
+    }
+    headers.push((
+        header::VARY,
+        String::from("Origin, Access-Control-Request-Method, Access-Control-Request-Headers"),
+    ));
+    Some(headers)
+}
+
+/// Headers added to the response of an actual request, empty if the origin is not allowed.
+pub fn response_headers(req: &RequestHeader, conf: &Cors) -> Vec<(HeaderName, String)> {
+    match origin(req).filter(|origin| conf.allows_origin(origin)) {
+        Some(origin) => {
+            let mut headers = allow_origin(origin, conf);
+            if !conf.expose_headers.is_empty() {
+                headers.push((
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    conf.expose_headers.join(", "),
+                ));
+            }
+            headers
+        }
+        None => Vec::new(),
+    }
+}
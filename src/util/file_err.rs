@@ -1,12 +1,9 @@
-use crate::util::mime::get_mime_type;
-use http::{header, StatusCode};
-use pingora::http::ResponseHeader;
-use pingora::prelude::Session;
+use http::StatusCode;
 
-pub static PAGE404: &str = r#"<!DOCTYPE html>
+static PAGE: &str = r#"<!DOCTYPE html>
 <html>
   <head>
-    <title>404</title>
+    <title>{title}</title>
     <style>
       html {
         color-scheme: light dark;
@@ -19,52 +16,42 @@ pub static PAGE404: &str = r#"<!DOCTYPE html>
     </style>
   </head>
   <body>
-    <h1>404 Not Found</h1>
+{content}{powered}  </body>
+</html>
+"#;
+
+static CONTENT404: &str = r#"    <h1>404 Not Found</h1>
     <p>
       The page you visited does not exist.
     </p>
-    <p><em>Powered by <a href="https://pingpong.bluemangoo.net/">Pingpong</a>.</em></p>
-  </body>
-</html>
 "#;
 
-pub static PAGE50X: &str = r#"<!DOCTYPE html>
-<html>
-  <head>
-    <title>An error occurred.</title>
-    <style>
-      html {
-        color-scheme: light dark;
-      }
-      body {
-        width: 35em;
-        margin: 0 auto;
-        font-family: Tahoma, Verdana, Arial, sans-serif;
-      }
-    </style>
-  </head>
-  <body>
-    <h1>An error occurred.</h1>
+static CONTENT50X: &str = r#"    <h1>An error occurred.</h1>
     <p>
       Sorry, the page you are looking for is currently unavailable.<br/>
       Please try again later.
     </p>
-    <p><em>Powered by <a href="https://pingpong.bluemangoo.net/">Pingpong</a>.</em></p>
-  </body>
-</html>
 "#;
 
-pub async fn make_page50x(session: &mut Session, status:StatusCode) -> pingora::Result<bool> {
-    let content_length = PAGE50X.len();
-    let mut resp = ResponseHeader::build(status, Some(4))?;
-    resp.insert_header(header::SERVER, "Pingpong")?;
-    resp.insert_header(header::CONTENT_LENGTH, content_length.to_string())?;
-    resp.insert_header(header::CONTENT_TYPE, get_mime_type(".html"))?;
-
-    session.write_response_header(Box::new(resp), false).await?;
+static POWERED: &str = r#"    <p><em>Powered by <a href="https://pingpong.bluemangoo.net/">Pingpong</a>.</em></p>
+"#;
 
-    session
-        .write_response_body(Some(PAGE50X.into()), true)
-        .await?;
-    Ok(true)
+/// Built-in page for `status`, the `Powered by` line is left out when `powered` is false.
+pub fn builtin_page(status: StatusCode, powered: bool) -> String {
+    let (title, content) = if status == StatusCode::NOT_FOUND {
+        (String::from("404"), String::from(CONTENT404))
+    } else if status.is_server_error() {
+        (String::from("An error occurred."), String::from(CONTENT50X))
+    } else {
+        let title = format!(
+            "{} {}",
+            status.as_str(),
+            status.canonical_reason().unwrap_or_default()
+        );
+        let content = format!("    <h1>{}</h1>\n", title);
+        (title, content)
+    };
+    PAGE.replace("{title}", &title)
+        .replace("{content}", &content)
+        .replace("{powered}", if powered { POWERED } else { "" })
 }
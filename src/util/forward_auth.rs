@@ -0,0 +1,120 @@
+use crate::config::ForwardAuth;
+use crate::util::forwarded::ForwardedInfo;
+use http::{header, HeaderName, HeaderValue, StatusCode};
+use pingora::connectors::http::Connector;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::HttpPeer;
+use pingora::Error;
+
+/// Max length of the body of an auth response passed to the client.
+const BODY_MAX_LENGTH: usize = 64 * 1024;
+
+/// Headers never copied between the original request, the subrequest and the responses.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+    "host",
+];
+
+pub enum AuthResult {
+    /// headers copied onto the upstream request
+    Allow(Vec<(HeaderName, Vec<HeaderValue>)>),
+    /// 401, 403 or redirect of the auth service, passed to the client
+    Deny(Box<ResponseHeader>, Vec<u8>),
+    /// any other status, not passed to the client
+    Unexpected(StatusCode),
+}
+
+/// Subrequest with the method and headers of the original request, along with `X-Forwarded-*`
+/// describing it.
+pub fn subrequest(
+    original: &RequestHeader,
+    original_uri: &str,
+    conf: &ForwardAuth,
+    host: &str,
+    uri: &str,
+    info: &ForwardedInfo,
+) -> pingora::Result<RequestHeader> {
+    let mut req = RequestHeader::build(original.method.clone(), uri.as_bytes(), None)?;
+    for (name, value) in original.headers.iter() {
+        let selected = match &conf.request_headers {
+            Some(names) => names.iter().any(|n| name.as_str().eq_ignore_ascii_case(n)),
+            None => !HOP_BY_HOP.contains(&name.as_str()),
+        };
+        if selected {
+            req.append_header(name.clone(), value.clone())?;
+        }
+    }
+    req.insert_header(header::HOST, host)?;
+    req.insert_header(header::CONTENT_LENGTH, "0")?;
+    req.insert_header("X-Forwarded-Method", original.method.as_str())?;
+    req.insert_header("X-Forwarded-Proto", info.proto)?;
+    req.insert_header("X-Forwarded-Host", info.host)?;
+    req.insert_header("X-Forwarded-Uri", original_uri)?;
+    match info.client_ip {
+        Some(ip) => req.insert_header("X-Forwarded-For", ip.to_string())?,
+        None => {
+            req.remove_header("X-Forwarded-For");
+        }
+    }
+    Ok(req)
+}
+
+/// Send the subrequest, allow on 2xx and deny with the response on 401, 403 or 3xx.
+pub async fn check(
+    connector: &Connector,
+    peer: &HttpPeer,
+    req: RequestHeader,
+    conf: &ForwardAuth,
+) -> pingora::Result<AuthResult> {
+    let (mut session, _) = connector.get_http_session(peer).await?;
+    session.write_request_header(Box::new(req)).await?;
+    session.finish_request_body().await?;
+    session.read_response_header().await?;
+    let resp = match session.response_header() {
+        Some(resp) => resp.clone(),
+        None => Err(Error::new_str("no response from auth service"))?,
+    };
+    let mut body = Vec::new();
+    while let Some(chunk) = session.read_response_body().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > BODY_MAX_LENGTH {
+            Err(Error::new_str("auth response too large"))?;
+        }
+    }
+    connector.release_http_session(session, peer, None).await;
+
+    if resp.status.is_success() {
+        return Ok(AuthResult::Allow(
+            conf.response_headers
+                .iter()
+                .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+                .map(|name| {
+                    let values = resp.headers.get_all(&name).iter().cloned().collect();
+                    (name, values)
+                })
+                .collect(),
+        ));
+    }
+    if !matches!(
+        resp.status,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+    ) && !resp.status.is_redirection()
+    {
+        return Ok(AuthResult::Unexpected(resp.status));
+    }
+
+    let mut denied = ResponseHeader::build(resp.status, Some(resp.headers.len()))?;
+    for (name, value) in resp.headers.iter() {
+        if !HOP_BY_HOP.contains(&name.as_str()) {
+            denied.append_header(name.clone(), value.clone())?;
+        }
+    }
+    Ok(AuthResult::Deny(Box::new(denied), body))
+}
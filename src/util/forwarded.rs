@@ -0,0 +1,184 @@
+use crate::config::ForwardedHeaders;
+use crate::util::ip;
+use pingora::http::RequestHeader;
+use std::net::IpAddr;
+
+pub struct ForwardedInfo<'a> {
+    /// address of the downstream peer, decides whether incoming values are trusted
+    pub peer_ip: Option<IpAddr>,
+    pub client_ip: Option<IpAddr>,
+    pub proto: &'a str,
+    pub host: &'a str,
+    pub port: u16,
+}
+
+fn node(ip: Option<IpAddr>) -> String {
+    match ip {
+        None => String::from("unknown"),
+        Some(IpAddr::V4(ip)) => ip.to_string(),
+        Some(IpAddr::V6(ip)) => format!("\"[{}]\"", ip),
+    }
+}
+
+fn header_value(header: &RequestHeader, name: &str) -> Option<String> {
+    let values = header
+        .headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<&str>>();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+fn set(
+    header: &mut RequestHeader,
+    name: &'static str,
+    value: String,
+    keep: bool,
+) -> pingora::Result<()> {
+    if keep && header.headers.contains_key(name) {
+        return Ok(());
+    }
+    header.insert_header(name, value)?;
+    Ok(())
+}
+
+/// Incoming chain kept from a trusted peer, or the client address when it is not the peer (taken
+/// from `real_ip_header`), followed by the peer, like `$proxy_add_x_forwarded_for` of nginx.
+fn chain(incoming: Option<String>, client: Option<String>, peer: String) -> String {
+    match incoming.or(client.filter(|client| *client != peer)) {
+        Some(incoming) => format!("{}, {}", incoming, peer),
+        None => peer,
+    }
+}
+
+/// Set forwarding headers, incoming values are kept only when the peer is a trusted proxy.
+pub fn apply(
+    header: &mut RequestHeader,
+    conf: &ForwardedHeaders,
+    info: &ForwardedInfo,
+) -> pingora::Result<()> {
+    let trusted = info
+        .peer_ip
+        .is_some_and(|peer| ip::contains(&conf.trusted_proxies, &peer));
+    let client = info.client_ip.map(|ip| ip.to_string());
+
+    if conf.x_forwarded() {
+        let incoming = header_value(header, "X-Forwarded-For").filter(|_| trusted);
+        let peer = info
+            .peer_ip
+            .map(|ip| ip.to_string())
+            .unwrap_or(String::from("unknown"));
+        let xff = chain(incoming, client.clone(), peer);
+        header.insert_header("X-Forwarded-For", xff)?;
+        let client = client.unwrap_or(String::from("unknown"));
+        set(header, "X-Real-IP", client, trusted)?;
+        set(
+            header,
+            "X-Forwarded-Proto",
+            String::from(info.proto),
+            trusted,
+        )?;
+        set(header, "X-Forwarded-Host", String::from(info.host), trusted)?;
+        set(header, "X-Forwarded-Port", info.port.to_string(), trusted)?;
+    }
+
+    if conf.forwarded() {
+        let element = |ip: Option<IpAddr>| {
+            let mut element = format!("for={};proto={}", node(ip), info.proto);
+            if !info.host.is_empty() {
+                element.push_str(&format!(";host=\"{}\"", info.host));
+            }
+            element
+        };
+        let incoming = header_value(header, "Forwarded").filter(|_| trusted);
+        let forwarded = chain(
+            incoming,
+            info.client_ip.map(|ip| element(Some(ip))),
+            element(info.peer_ip),
+        );
+        header.insert_header("Forwarded", forwarded)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, ForwardedInfo};
+    use crate::config::{ForwardedHeaders, RealIp};
+    use crate::util::ip;
+    use pingora::http::RequestHeader;
+    use std::net::IpAddr;
+
+    fn request(xff: &str) -> RequestHeader {
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        header.insert_header("X-Forwarded-For", xff).unwrap();
+        header
+    }
+
+    fn forwarded(header: &mut RequestHeader, peer: IpAddr, trusted: &[&str]) {
+        let real_ip = RealIp::from_raw(
+            Some(String::from("X-Forwarded-For")),
+            Some(vec![String::from("10.0.0.0/8")]),
+            "test.toml",
+        )
+        .unwrap()
+        .unwrap();
+        let conf = ForwardedHeaders::from_raw(
+            Some(String::from("all")),
+            Some(trusted.iter().map(|t| String::from(*t)).collect()),
+            "test.toml",
+        )
+        .unwrap()
+        .unwrap();
+        let info = ForwardedInfo {
+            peer_ip: Some(peer),
+            client_ip: Some(ip::real_ip_of(peer, header, &real_ip)),
+            proto: "https",
+            host: "a.com",
+            port: 443,
+        };
+        apply(header, &conf, &info).unwrap();
+    }
+
+    fn value<'a>(header: &'a RequestHeader, name: &str) -> &'a str {
+        header.headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn real_ip_appends_peer() {
+        let peer = "10.0.0.2".parse().unwrap();
+
+        let mut header = request("203.0.113.7, 10.0.0.1");
+        forwarded(&mut header, peer, &["10.0.0.0/8"]);
+        assert_eq!(
+            value(&header, "X-Forwarded-For"),
+            "203.0.113.7, 10.0.0.1, 10.0.0.2"
+        );
+        assert_eq!(value(&header, "X-Real-IP"), "203.0.113.7");
+        // no incoming `Forwarded`, so it starts with the client
+        assert_eq!(
+            value(&header, "Forwarded"),
+            "for=203.0.113.7;proto=https;host=\"a.com\", for=10.0.0.2;proto=https;host=\"a.com\""
+        );
+
+        // incoming chain is dropped, the client from real_ip is kept before the peer
+        let mut header = request("203.0.113.7, 10.0.0.1");
+        forwarded(&mut header, peer, &[]);
+        assert_eq!(value(&header, "X-Forwarded-For"), "203.0.113.7, 10.0.0.2");
+        assert_eq!(
+            value(&header, "Forwarded"),
+            "for=203.0.113.7;proto=https;host=\"a.com\", for=10.0.0.2;proto=https;host=\"a.com\""
+        );
+
+        // a peer not in real_ip_from is the client
+        let mut header = request("203.0.113.7");
+        forwarded(&mut header, "198.51.100.1".parse().unwrap(), &[]);
+        assert_eq!(value(&header, "X-Forwarded-For"), "198.51.100.1");
+        assert_eq!(value(&header, "X-Real-IP"), "198.51.100.1");
+    }
+}
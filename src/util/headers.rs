@@ -0,0 +1,63 @@
+use crate::config::{HeaderMode, HeaderRule};
+use http::header;
+use pingora::http::{RequestHeader, ResponseHeader};
+
+/// Remove headers, then apply rendered rules to the request to upstream.
+pub fn apply_request(
+    req: &mut RequestHeader,
+    remove: &[String],
+    rules: Vec<(&HeaderRule, String)>,
+) -> pingora::Result<()> {
+    for name in remove {
+        req.remove_header(name.as_str());
+    }
+    let content_type = req.headers.get(header::CONTENT_TYPE).cloned();
+    for (rule, value) in rules {
+        if !rule.is_match(None, content_type.as_ref()) {
+            continue;
+        }
+        match rule.mode {
+            HeaderMode::Set => req.insert_header(rule.name.clone(), value)?,
+            HeaderMode::Append => {
+                req.append_header(rule.name.clone(), value)?;
+            }
+            HeaderMode::Default => {
+                if !req.headers.contains_key(rule.name.as_str()) {
+                    req.insert_header(rule.name.clone(), value)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove headers, then apply rendered rules to the response, conditions are checked against
+/// the status and content type of it.
+pub fn apply_response(
+    resp: &mut ResponseHeader,
+    remove: &[String],
+    rules: Vec<(&HeaderRule, String)>,
+) -> pingora::Result<()> {
+    for name in remove {
+        resp.remove_header(name.as_str());
+    }
+    let status = resp.status;
+    let content_type = resp.headers.get(header::CONTENT_TYPE).cloned();
+    for (rule, value) in rules {
+        if !rule.is_match(Some(status), content_type.as_ref()) {
+            continue;
+        }
+        match rule.mode {
+            HeaderMode::Set => resp.insert_header(rule.name.clone(), value)?,
+            HeaderMode::Append => {
+                resp.append_header(rule.name.clone(), value)?;
+            }
+            HeaderMode::Default => {
+                if !resp.headers.contains_key(rule.name.as_str()) {
+                    resp.insert_header(rule.name.clone(), value)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use anyhow::anyhow;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::Engine;
+use log::{error, info};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// Users of an htpasswd file, reloaded when the file is modified.
+pub struct UserFile {
+    path: String,
+    state: RwLock<(Option<SystemTime>, HashMap<String, String>)>,
+}
+
+impl Debug for UserFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl UserFile {
+    pub fn load(path: String) -> anyhow::Result<Self> {
+        let modified = modified(&path);
+        let users = parse(&path)?;
+        Ok(Self {
+            path,
+            state: RwLock::new((modified, users)),
+        })
+    }
+
+    fn reload(&self) {
+        let modified = modified(&self.path);
+        if self.state.read().unwrap().0 == modified {
+            return;
+        }
+        match parse(&self.path) {
+            Ok(users) => {
+                info!("Reloaded user file {}", self.path);
+                *self.state.write().unwrap() = (modified, users);
+            }
+            Err(e) => {
+                error!("Failed to reload user file, keep the old one: {}", e);
+                self.state.write().unwrap().0 = modified;
+            }
+        }
+    }
+
+    /// Blocking, as the hashes are slow on purpose.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        self.reload();
+        let (hash, known) = {
+            let users = &self.state.read().unwrap().1;
+            match users.get(user) {
+                Some(hash) => (hash.clone(), true),
+                // check another hash anyway, so unknown users take as long as known ones
+                None => match users.values().next() {
+                    Some(hash) => (hash.clone(), false),
+                    None => return false,
+                },
+            }
+        };
+        verify_hash(&hash, password) && known
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn parse(path: &str) -> anyhow::Result<HashMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("{} Failed to read user file: {}", path, e))?;
+    let mut users = HashMap::new();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (user, hash) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => Err(anyhow!("{}:{} Wrong syntax: expect user:hash", path, n + 1))?,
+        };
+        if !is_supported(hash) {
+            Err(anyhow!(
+                "{}:{} Wrong syntax: unsupported hash of {}, use bcrypt, SHA-crypt or argon2",
+                path,
+                n + 1,
+                user
+            ))?;
+        }
+        users.insert(String::from(user), String::from(hash));
+    }
+    Ok(users)
+}
+
+fn is_supported(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$", "$5$", "$6$", "$argon2"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+fn verify_hash(hash: &str, password: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => false,
+        }
+    } else if hash.starts_with("$5$") {
+        pwhash::sha256_crypt::verify(password, hash)
+    } else if hash.starts_with("$6$") {
+        pwhash::sha512_crypt::verify(password, hash)
+    } else {
+        pwhash::bcrypt::verify(password, hash)
+    }
+}
+
+/// Decode `Authorization: Basic ...` into user and password.
+pub fn basic_credentials(authorization: &[u8]) -> Option<(String, String)> {
+    let value = std::str::from_utf8(authorization).ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(token.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((String::from(user), String::from(password)))
+}
@@ -0,0 +1,71 @@
+use crate::config::RealIp;
+use anyhow::anyhow;
+use ipnet::IpNet;
+use pingora::http::RequestHeader;
+use pingora::prelude::Session;
+use std::net::IpAddr;
+
+/// Parse `10.0.0.0/8`, `::1/128` or a bare address into a network.
+pub fn parse_net(net: &str, path: &str) -> anyhow::Result<IpNet> {
+    if let Ok(net) = net.parse::<IpNet>() {
+        return Ok(net);
+    }
+    net.parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|_| anyhow!("{} Wrong syntax: {} is not an ip or cidr", path, net))
+}
+
+pub fn parse_nets(nets: &[String], path: &str) -> anyhow::Result<Vec<IpNet>> {
+    nets.iter().map(|net| parse_net(net, path)).collect()
+}
+
+pub fn contains(nets: &[IpNet], ip: &IpAddr) -> bool {
+    nets.iter().any(|net| net.contains(ip))
+}
+
+/// Address of the downstream peer, ipv4-mapped ipv6 addresses are converted to ipv4.
+pub fn peer_ip(session: &Session) -> Option<IpAddr> {
+    session
+        .client_addr()
+        .and_then(|addr| addr.as_inet())
+        .map(|addr| addr.ip().to_canonical())
+}
+
+/// Client address from `real_ip_header` when the peer is trusted. For a list like
+/// `X-Forwarded-For`, the last address not in `real_ip_from` is taken.
+pub fn real_ip(session: &Session, real_ip: &RealIp) -> Option<IpAddr> {
+    Some(real_ip_of(peer_ip(session)?, session.req_header(), real_ip))
+}
+
+/// Same as [`real_ip`], for the request `header` received from `peer`.
+pub fn real_ip_of(peer: IpAddr, header: &RequestHeader, real_ip: &RealIp) -> IpAddr {
+    if !contains(&real_ip.from, &peer) {
+        return peer;
+    }
+    let addrs = header
+        .headers
+        .get_all(&real_ip.header)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|addr| parse_addr(addr.trim()))
+        .collect::<Vec<IpAddr>>();
+    let mut result = peer;
+    for addr in addrs.into_iter().rev() {
+        result = addr;
+        if !contains(&real_ip.from, &addr) {
+            break;
+        }
+    }
+    result
+}
+
+/// Parse `192.0.2.1`, `192.0.2.1:80`, `2001:db8::1` or `[2001:db8::1]:80`.
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    addr.parse::<std::net::SocketAddr>()
+        .ok()
+        .map(|addr| addr.ip().to_canonical())
+}
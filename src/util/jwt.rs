@@ -0,0 +1,93 @@
+use crate::config::Jwt;
+use crate::util::route;
+use http::{header, HeaderName, HeaderValue};
+use jsonwebtoken::{decode, decode_header, Validation};
+use pingora::http::RequestHeader;
+use serde_json::{Map, Value};
+
+pub type Claims = Map<String, Value>;
+
+/// Token from `Authorization: Bearer`, then the cookie, then the query parameter.
+pub fn token(req: &RequestHeader, conf: &Jwt) -> Option<String> {
+    if let Some(value) = req.headers.get(header::AUTHORIZATION) {
+        if let Some((scheme, token)) = value.to_str().ok()?.trim().split_once(' ') {
+            if scheme.eq_ignore_ascii_case("bearer") {
+                return Some(String::from(token.trim()));
+            }
+        }
+    }
+    if let Some(name) = &conf.token_cookie {
+        if let Some((_, token)) = route::cookies(req).into_iter().find(|(k, _)| k == name) {
+            return Some(String::from(token));
+        }
+    }
+    if let Some(name) = &conf.token_query {
+        if let Some((_, token)) = route::query(req).into_iter().find(|(k, _)| k == name) {
+            return Some(token);
+        }
+    }
+    None
+}
+
+/// Values of a claim, the elements for an array.
+pub fn claim_values(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(list) => list.iter().flat_map(claim_values).collect(),
+        Value::Null => Vec::new(),
+        other => vec![other.to_string()],
+    }
+}
+
+/// Verify the signature and the registered claims, then the required claims.
+pub fn validate(token: &str, conf: &Jwt) -> Result<Claims, String> {
+    let header = decode_header(token).map_err(|e| e.to_string())?;
+    let mut error = String::from("no key for the token");
+    for key in conf.keys.iter().filter(|key| {
+        key.algorithm == header.alg
+            && (key.kid.is_none() || header.kid.is_none() || key.kid == header.kid)
+    }) {
+        let mut validation = Validation::new(key.algorithm);
+        validation.leeway = conf.leeway;
+        validation.validate_nbf = true;
+        match &conf.issuer {
+            Some(issuer) => validation.set_issuer(issuer),
+            None => validation.iss = None,
+        }
+        match &conf.audience {
+            Some(audience) => validation.set_audience(audience),
+            None => validation.validate_aud = false,
+        }
+        match decode::<Claims>(token, &key.key, &validation) {
+            Ok(data) => {
+                for (name, value) in &conf.claims {
+                    let values = data.claims.get(name).map(claim_values).unwrap_or_default();
+                    if !values.iter().any(|v| value.is_match(v)) {
+                        return Err(format!("claim {} not matched", name));
+                    }
+                }
+                return Ok(data.claims);
+            }
+            Err(e) => error = e.to_string(),
+        }
+    }
+    Err(error)
+}
+
+/// Headers carrying the claims to upstream service, empty ones remove the header.
+pub fn claims_headers(claims: &Claims, conf: &Jwt) -> Vec<(HeaderName, Vec<HeaderValue>)> {
+    conf.claims_headers
+        .iter()
+        .filter_map(|(name, claim)| {
+            let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+            let values = claims
+                .get(claim)
+                .map(claim_values)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|v| HeaderValue::from_str(&v).ok())
+                .collect();
+            Some((name, values))
+        })
+        .collect()
+}
@@ -0,0 +1,62 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGauge, IntGaugeVec,
+};
+
+static IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pingpong_requests_in_flight",
+        "Requests being handled",
+        &["port", "source"]
+    )
+    .unwrap()
+});
+
+static REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pingpong_concurrency_rejected_total",
+        "Requests rejected by concurrency limits",
+        &["port", "source", "limit"]
+    )
+    .unwrap()
+});
+
+static WAF_MATCHED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pingpong_waf_matched_total",
+        "Requests matched by waf rules",
+        &["port", "source", "rule", "action"]
+    )
+    .unwrap()
+});
+
+/// Counts a request as in flight until dropped.
+pub struct InFlight {
+    gauge: IntGauge,
+}
+
+impl InFlight {
+    pub fn new(port: u16, source: &str) -> Self {
+        let gauge = IN_FLIGHT.with_label_values(&[&port.to_string(), source]);
+        gauge.inc();
+        Self { gauge }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+pub fn rejected(port: u16, source: &str, limit: &str) {
+    REJECTED
+        .with_label_values(&[&port.to_string(), source, limit])
+        .inc();
+}
+
+pub fn waf_matched(port: u16, source: &str, rule: &str, action: &str) {
+    WAF_MATCHED
+        .with_label_values(&[&port.to_string(), source, rule, action])
+        .inc();
+}
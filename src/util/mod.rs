@@ -3,3 +3,17 @@ pub mod route;
 pub mod url;
 pub mod mime;
 pub mod file_err;
+pub mod template;
+pub mod ip;
+pub mod forwarded;
+pub mod headers;
+pub mod proxy_protocol;
+pub mod htpasswd;
+pub mod forward_auth;
+pub mod jwt;
+pub mod secure_link;
+pub mod rate_limit;
+pub mod concurrency;
+pub mod metrics;
+pub mod cors;
+pub mod waf;
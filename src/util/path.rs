@@ -1,6 +1,6 @@
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Component, Path};
 use std::{fs, io};
 
 pub fn resolve(base: &str, path: &str) -> String {
@@ -38,6 +38,13 @@ pub fn resolve_uri(base: &str, path: &str) -> String {
     }
 }
 
+/// Whether a decoded uri path steps out of the directory with `..`.
+pub fn has_parent(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .any(|component| component == Component::ParentDir)
+}
+
 pub fn create(path: &str) -> io::Result<()> {
     if File::open(path).is_ok() {
         return Ok(());
@@ -0,0 +1,359 @@
+use crate::config::ProxyProtocolVersion;
+use async_trait::async_trait;
+use pingora::connectors::L4Connect;
+use pingora::protocols::l4::socket::SocketAddr as L4SocketAddr;
+use pingora::protocols::l4::stream::Stream;
+use pingora::{Error, ErrorType, OrErr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// Signature of PROXY protocol v2.
+pub const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8; 6] = b"PROXY ";
+/// Max length of a v1 header, including `\r\n`.
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Debug, PartialEq)]
+pub struct ProxyHeader {
+    /// Addresses of the original connection, `None` for `LOCAL` or `UNKNOWN` ones.
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Parsed {
+    /// More bytes are required.
+    Incomplete,
+    /// The connection does not start with a PROXY header.
+    NotProxy,
+    /// A header and its length in bytes.
+    Header(ProxyHeader, usize),
+}
+
+/// Parse a PROXY protocol v1 or v2 header from the beginning of `buf`.
+pub fn parse(buf: &[u8]) -> Result<Parsed, String> {
+    if buf.starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if V1_PREFIX.starts_with(buf) || V2_SIGNATURE.starts_with(buf) {
+        Ok(Parsed::Incomplete)
+    } else {
+        Ok(Parsed::NotProxy)
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Parsed, String> {
+    let end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None if buf.len() >= V1_MAX_LENGTH => Err("PROXY v1 header too long")?,
+        None => return Ok(Parsed::Incomplete),
+    };
+    if end + 2 > V1_MAX_LENGTH {
+        Err("PROXY v1 header too long")?;
+    }
+    let line = std::str::from_utf8(&buf[..end]).map_err(|_| "invalid PROXY v1 header")?;
+    let parts = line.split(' ').collect::<Vec<&str>>();
+    let header = match parts.get(1) {
+        Some(&"UNKNOWN") => ProxyHeader {
+            source: None,
+            destination: None,
+        },
+        Some(&proto @ ("TCP4" | "TCP6")) if parts.len() == 6 => {
+            let ip = |s: &str| -> Result<IpAddr, String> {
+                let ip = s
+                    .parse::<IpAddr>()
+                    .map_err(|_| format!("invalid address {} in PROXY v1 header", s))?;
+                if ip.is_ipv4() != (proto == "TCP4") {
+                    Err(format!("address {} does not match {}", s, proto))?;
+                }
+                Ok(ip)
+            };
+            let port = |s: &str| -> Result<u16, String> {
+                s.parse::<u16>()
+                    .map_err(|_| format!("invalid port {} in PROXY v1 header", s))
+            };
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(parts[2])?, port(parts[4])?)),
+                destination: Some(SocketAddr::new(ip(parts[3])?, port(parts[5])?)),
+            }
+        }
+        _ => Err(format!("invalid PROXY v1 header: {}", line))?,
+    };
+    Ok(Parsed::Header(header, end + 2))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Parsed, String> {
+    if buf.len() < 16 {
+        return Ok(Parsed::Incomplete);
+    }
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0f;
+    let family = buf[13];
+    let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if version != 2 {
+        Err(format!("unsupported PROXY protocol version {}", version))?;
+    }
+    if buf.len() < 16 + length {
+        return Ok(Parsed::Incomplete);
+    }
+    let addr = &buf[16..16 + length];
+    let header = match (command, family >> 4) {
+        // LOCAL, like health checks from the proxy itself
+        (0x0, _) => ProxyHeader {
+            source: None,
+            destination: None,
+        },
+        (0x1, 0x1) if addr.len() >= 12 => {
+            let ip = |i: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    addr[i],
+                    addr[i + 1],
+                    addr[i + 2],
+                    addr[i + 3],
+                ))
+            };
+            let port = |i: usize| u16::from_be_bytes([addr[i], addr[i + 1]]);
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(8))),
+                destination: Some(SocketAddr::new(ip(4), port(10))),
+            }
+        }
+        (0x1, 0x2) if addr.len() >= 36 => {
+            let ip = |i: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addr[i..i + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |i: usize| u16::from_be_bytes([addr[i], addr[i + 1]]);
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(32))),
+                destination: Some(SocketAddr::new(ip(16), port(34))),
+            }
+        }
+        // unspecified or unix sockets, keep the address of the connection
+        (0x1, 0x0 | 0x3) => ProxyHeader {
+            source: None,
+            destination: None,
+        },
+        _ => Err(format!(
+            "invalid PROXY v2 header, command {}, family {}",
+            command, family
+        ))?,
+    };
+    Ok(Parsed::Header(header, 16 + length))
+}
+
+/// Encode a PROXY header, a `LOCAL` (v2) or `UNKNOWN` (v1) one if any address is unknown.
+pub fn encode(
+    version: ProxyProtocolVersion,
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>,
+) -> Vec<u8> {
+    let addrs = match (source, destination) {
+        (Some(s), Some(d)) if s.is_ipv4() == d.is_ipv4() => Some((s, d)),
+        // both addresses must be in the same family
+        (Some(s), Some(d)) => Some((to_ipv6(s), to_ipv6(d))),
+        _ => None,
+    };
+    match version {
+        ProxyProtocolVersion::V1 => match addrs {
+            None => Vec::from(&b"PROXY UNKNOWN\r\n"[..]),
+            Some((s, d)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if s.is_ipv4() { "TCP4" } else { "TCP6" },
+                s.ip(),
+                d.ip(),
+                s.port(),
+                d.port()
+            )
+            .into_bytes(),
+        },
+        ProxyProtocolVersion::V2 => {
+            let mut header = Vec::from(&V2_SIGNATURE[..]);
+            match addrs {
+                None => header.extend_from_slice(&[0x20, 0x00, 0, 0]),
+                Some((s, d)) => {
+                    let (family, mut addr) = match (s.ip(), d.ip()) {
+                        (IpAddr::V4(sip), IpAddr::V4(dip)) => {
+                            (0x11, [sip.octets(), dip.octets()].concat())
+                        }
+                        (sip, dip) => (0x21, [to_octets(sip), to_octets(dip)].concat()),
+                    };
+                    addr.extend_from_slice(&s.port().to_be_bytes());
+                    addr.extend_from_slice(&d.port().to_be_bytes());
+                    header.extend_from_slice(&[0x21, family]);
+                    header.extend_from_slice(&(addr.len() as u16).to_be_bytes());
+                    header.extend_from_slice(&addr);
+                }
+            }
+            header
+        }
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+fn to_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => Vec::from(ip.to_ipv6_mapped().octets()),
+        IpAddr::V6(ip) => Vec::from(ip.octets()),
+    }
+}
+
+/// Connect to upstream services and send a PROXY header before anything else.
+#[derive(Debug)]
+pub struct ProxyProtocolConnect {
+    pub header: Vec<u8>,
+    pub timeout: Option<Duration>,
+}
+
+impl ProxyProtocolConnect {
+    async fn connect_inet(&self, addr: SocketAddr) -> pingora::Result<TcpStream> {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .or_err(ErrorType::ConnectError, "failed to connect upstream")?;
+        stream
+            .write_all(&self.header)
+            .await
+            .or_err(ErrorType::WriteError, "failed to send PROXY header")?;
+        Ok(stream)
+    }
+}
+
+#[async_trait]
+impl L4Connect for ProxyProtocolConnect {
+    async fn connect(&self, addr: &L4SocketAddr) -> pingora::Result<Stream> {
+        let addr = match addr.as_inet() {
+            Some(addr) => *addr,
+            None => Err(Error::explain(
+                ErrorType::SocketError,
+                "PROXY protocol requires an inet upstream",
+            ))?,
+        };
+        let stream = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.connect_inet(addr)).await {
+                Ok(stream) => stream?,
+                Err(_) => Err(Error::explain(
+                    ErrorType::ConnectTimedout,
+                    format!("timeout {:?} connecting to server {}", timeout, addr),
+                ))?,
+            },
+            None => self.connect_inet(addr).await?,
+        };
+        Ok(stream.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    /// Accept one connection and parse the PROXY header from it.
+    async fn stub(listener: TcpListener) -> (ProxyHeader, Vec<u8>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        loop {
+            let mut chunk = [0u8; 64];
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert_ne!(n, 0, "connection closed before PROXY header");
+            buf.extend_from_slice(&chunk[..n]);
+            match parse(&buf).unwrap() {
+                Parsed::Incomplete => continue,
+                Parsed::NotProxy => panic!("not a PROXY header: {:?}", buf),
+                Parsed::Header(header, length) => return (header, buf.split_off(length)),
+            }
+        }
+    }
+
+    async fn send_through_stub(
+        version: ProxyProtocolVersion,
+        source: Option<SocketAddr>,
+        destination: Option<SocketAddr>,
+    ) -> (ProxyHeader, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = L4SocketAddr::Inet(listener.local_addr().unwrap());
+        let stub = tokio::spawn(stub(listener));
+        let connect = ProxyProtocolConnect {
+            header: encode(version, source, destination),
+            timeout: Some(Duration::from_secs(3)),
+        };
+        let mut stream = connect.connect(&upstream).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        stream.flush().await.unwrap();
+        stub.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_send_v1() {
+        let (header, rest) = send_through_stub(
+            ProxyProtocolVersion::V1,
+            Some(addr("203.0.113.7:5555")),
+            Some(addr("192.0.2.1:443")),
+        )
+        .await;
+        assert_eq!(header.source, Some(addr("203.0.113.7:5555")));
+        assert_eq!(header.destination, Some(addr("192.0.2.1:443")));
+        assert!(b"GET / HTTP/1.1\r\n".starts_with(&rest));
+    }
+
+    #[tokio::test]
+    async fn test_send_v2() {
+        let (header, _) = send_through_stub(
+            ProxyProtocolVersion::V2,
+            Some(addr("[2001:db8::7]:5555")),
+            Some(addr("[2001:db8::1]:443")),
+        )
+        .await;
+        assert_eq!(header.source, Some(addr("[2001:db8::7]:5555")));
+        assert_eq!(header.destination, Some(addr("[2001:db8::1]:443")));
+    }
+
+    #[tokio::test]
+    async fn test_send_v2_mixed_family() {
+        let (header, _) = send_through_stub(
+            ProxyProtocolVersion::V2,
+            Some(addr("203.0.113.7:5555")),
+            Some(addr("[2001:db8::1]:443")),
+        )
+        .await;
+        assert_eq!(header.source, Some(addr("[::ffff:203.0.113.7]:5555")));
+    }
+
+    #[tokio::test]
+    async fn test_send_unknown() {
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let (header, _) = send_through_stub(version, None, None).await;
+            assert_eq!(header.source, None);
+            assert_eq!(header.destination, None);
+        }
+    }
+
+    #[test]
+    fn test_parse_partial() {
+        let header = encode(
+            ProxyProtocolVersion::V2,
+            Some(addr("203.0.113.7:5555")),
+            Some(addr("192.0.2.1:443")),
+        );
+        for i in 0..header.len() {
+            assert_eq!(parse(&header[..i]), Ok(Parsed::Incomplete));
+        }
+        assert!(matches!(parse(&header), Ok(Parsed::Header(_, 28))));
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n"), Ok(Parsed::NotProxy));
+        assert!(parse(b"PROXY TCP4 203.0.113.7 ::1 5555 443\r\n").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Shards of the buckets, to spread the lock between keys.
+const SHARDS: usize = 16;
+/// Keys kept in a shard.
+const SHARD_KEYS: usize = 4096;
+/// Least recently used keys looked at for a full bucket to drop for a new key.
+const EVICT_SCAN: usize = 32;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    /// position in `Shard::recent`
+    stamp: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * rate).min(burst);
+        self.last = now;
+    }
+}
+
+#[derive(Default)]
+struct Shard {
+    buckets: HashMap<String, Bucket>,
+    /// keys from the least to the most recently used
+    recent: BTreeMap<u64, String>,
+    stamp: u64,
+}
+
+/// Token buckets keyed by client, header value or so, at most `SHARDS * SHARD_KEYS` of them.
+///
+/// Only full buckets are dropped for new keys, as a new bucket would be the same, so made-up
+/// keys cannot reset the bucket of a limited client. New keys are limited while the oldest
+/// buckets are not full.
+#[derive(Default)]
+pub struct Buckets {
+    shards: [Mutex<Shard>; SHARDS],
+    hasher: RandomState,
+}
+
+impl Debug for Buckets {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Buckets").finish()
+    }
+}
+
+impl Buckets {
+    /// Take a token from the bucket of `key`, which refills `rate` tokens per second up to
+    /// `burst`. Returns the tokens left and the seconds to refill them all, or the seconds to
+    /// wait for the next one.
+    pub fn acquire(&self, key: &str, rate: f64, burst: u32) -> Result<(u32, f64), f64> {
+        let now = Instant::now();
+        let burst = burst as f64;
+        let shard = &self.shards[self.hasher.hash_one(key) as usize % SHARDS];
+        let mut shard = shard.lock().unwrap();
+        let Shard {
+            buckets,
+            recent,
+            stamp,
+        } = &mut *shard;
+        if buckets.len() >= SHARD_KEYS && !buckets.contains_key(key) {
+            let mut wait = f64::MAX;
+            let mut full = None;
+            for (stamp, oldest) in recent.iter().take(EVICT_SCAN) {
+                let bucket = buckets.get_mut(oldest).unwrap();
+                bucket.refill(now, rate, burst);
+                if bucket.tokens >= burst {
+                    full = Some((*stamp, oldest.clone()));
+                    break;
+                }
+                wait = wait.min((burst - bucket.tokens) / rate);
+            }
+            match full {
+                Some((stamp, oldest)) => {
+                    recent.remove(&stamp);
+                    buckets.remove(&oldest);
+                }
+                None => return Err(wait),
+            }
+        }
+        *stamp += 1;
+        let bucket = buckets.entry(String::from(key)).or_insert(Bucket {
+            tokens: burst,
+            last: now,
+            stamp: 0,
+        });
+        recent.remove(&bucket.stamp);
+        bucket.stamp = *stamp;
+        recent.insert(*stamp, String::from(key));
+
+        bucket.refill(now, rate, burst);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok((bucket.tokens as u32, (burst - bucket.tokens) / rate))
+        } else {
+            Err((1.0 - bucket.tokens) / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_capped(buckets: &Buckets) {
+        for shard in &buckets.shards {
+            let shard = shard.lock().unwrap();
+            assert!(shard.buckets.len() <= SHARD_KEYS);
+            assert_eq!(shard.buckets.len(), shard.recent.len());
+        }
+    }
+
+    #[test]
+    fn full_buckets_are_dropped() {
+        let buckets = Buckets::default();
+        for i in 0..SHARDS * SHARD_KEYS * 2 {
+            buckets.acquire(&i.to_string(), 1e9, 1).unwrap();
+        }
+        assert_capped(&buckets);
+    }
+
+    #[test]
+    fn limited_buckets_are_kept() {
+        let buckets = Buckets::default();
+        buckets.acquire("limited", 1.0 / 3600.0, 1).unwrap();
+        let mut refused = 0;
+        for i in 0..SHARDS * SHARD_KEYS * 2 {
+            if buckets.acquire(&i.to_string(), 1.0 / 3600.0, 1).is_err() {
+                refused += 1;
+            }
+        }
+        assert!(refused >= SHARDS * SHARD_KEYS);
+        assert_capped(&buckets);
+        assert!(buckets.acquire("limited", 1.0 / 3600.0, 1).is_err());
+    }
+}
@@ -1,13 +1,109 @@
+use http::header;
+use pingora::http::RequestHeader;
 use pingora::{Error, HTTPStatus};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::path::Path;
+use urlencoding::decode;
 
-use crate::config::{Location, Proxy, Source, StaticServer};
+use crate::config::{Location, Proxy, Sni, Source, StaticServer};
 use crate::gateway::GatewayCTX;
-use crate::util::path;
+use crate::util::{ip, path};
+
+/// Sources grouped by sni, along with the sni patterns to look them up.
+pub struct Routes {
+    sources: HashMap<String, HashMap<String, Source>>,
+    // wildcard and regex patterns, in precedence order
+    patterns: Vec<(Sni, String)>,
+}
+
+impl Routes {
+    pub fn new(sources: HashMap<String, HashMap<String, Source>>) -> anyhow::Result<Self> {
+        let mut suffix = Vec::new();
+        let mut prefix = Vec::new();
+        let mut regex = Vec::new();
+        for key in sources.keys() {
+            if key.is_empty() {
+                continue;
+            }
+            match Sni::new(key, "sni")? {
+                Sni::Exact(_) => {}
+                Sni::Suffix(s) => suffix.push((Sni::Suffix(s), key.clone())),
+                Sni::Prefix(p) => prefix.push((Sni::Prefix(p), key.clone())),
+                Sni::Regex(re) => regex.push((Sni::Regex(re), key.clone())),
+            }
+        }
+        // the longest wildcard wins, regexes are tried in alphabetical order
+        suffix.sort_by_key(|s| std::cmp::Reverse(s.1.len()));
+        prefix.sort_by_key(|p| std::cmp::Reverse(p.1.len()));
+        regex.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut patterns = suffix;
+        patterns.append(&mut prefix);
+        patterns.append(&mut regex);
+        Ok(Self { sources, patterns })
+    }
+
+    pub fn get(&self, sni: &str) -> Option<&HashMap<String, Source>> {
+        self.sources.get(sni)
+    }
+
+    /// Sni groups matching `host` with their captures, in the order of exact, wildcard, regex and
+    /// the default one.
+    pub fn resolve(&self, host: &str) -> Vec<(&String, Vec<(String, String)>)> {
+        let host = host.to_lowercase();
+        let hostname = match host.rsplit_once(':') {
+            Some((h, port)) if !port.contains(']') => h,
+            _ => host.as_str(),
+        };
+        let mut result = Vec::new();
+        for h in [host.as_str(), hostname] {
+            if let Some((key, _)) = self.sources.get_key_value(h) {
+                if !key.is_empty() && !result.iter().any(|r: &(&String, _)| r.0 == key) {
+                    result.push((key, Vec::new()));
+                }
+            }
+        }
+        for (sni, key) in &self.patterns {
+            if let Some(captures) = sni.captures(hostname) {
+                result.push((key, captures));
+            }
+        }
+        if let Some((key, _)) = self.sources.get_key_value("") {
+            result.push((key, Vec::new()));
+        }
+        result
+    }
+}
+
+/// Cookies of the request as name and value.
+pub fn cookies(req: &RequestHeader) -> Vec<(&str, &str)> {
+    req.headers
+        .get_all(header::COOKIE)
+        .iter()
+        .flat_map(|v| v.to_str().unwrap_or_default().split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .collect()
+}
+
+/// Decoded query parameters of the request.
+pub fn query(req: &RequestHeader) -> Vec<(String, String)> {
+    req.uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .map(|q| q.split_once('=').unwrap_or((q, "")))
+        .map(|(k, v)| {
+            (
+                decode(k).map(|k| k.into_owned()).unwrap_or(String::from(k)),
+                decode(v).map(|v| v.into_owned()).unwrap_or(String::from(v)),
+            )
+        })
+        .collect()
+}
 
 pub fn match_route(uri: &str, source: &Source) -> bool {
-    let location = source.location_as_ref();
+    let location = &source.options().location;
     for loc in location {
         if match loc {
             Location::Start(l) => uri.starts_with(l),
@@ -20,17 +116,110 @@ pub fn match_route(uri: &str, source: &Source) -> bool {
     false
 }
 
+/// Check the optional conditions of `source`, all of them must hold.
+pub fn match_condition(source: &Source, req: &RequestHeader, client_ip: Option<IpAddr>) -> bool {
+    let condition = match &source.options().condition {
+        None => return true,
+        Some(condition) => condition,
+    };
+    if let Some(method) = &condition.method {
+        if !method.contains(&req.method) {
+            return false;
+        }
+    }
+    for (name, value) in &condition.header {
+        if !req
+            .headers
+            .get_all(name)
+            .iter()
+            .any(|v| value.is_match(v.to_str().unwrap_or_default()))
+        {
+            return false;
+        }
+    }
+    if !condition.cookie.is_empty() {
+        let cookies = cookies(req);
+        for (name, value) in &condition.cookie {
+            if !cookies.iter().any(|(k, v)| k == name && value.is_match(v)) {
+                return false;
+            }
+        }
+    }
+    if !condition.query.is_empty() {
+        let query = query(req);
+        for (name, value) in &condition.query {
+            if !query.iter().any(|(k, v)| k == name && value.is_match(v)) {
+                return false;
+            }
+        }
+    }
+    if let Some(client) = &condition.client {
+        match client_ip {
+            Some(client_ip) if ip::contains(client, &client_ip) => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Sources with conditions take precedence over the ones without.
+fn has_condition(source: Option<(&String, &Source)>) -> bool {
+    source.is_some_and(|s| s.1.options().condition.is_some())
+}
+
+/// Captures of the first regex location matching `uri`, named like `1` and `<name>`.
+pub fn match_captures(uri: &str, source: &Source) -> Vec<(String, String)> {
+    for loc in &source.options().location {
+        if let Location::Regex(re) = loc {
+            if let Some(captures) = re.captures(uri) {
+                let mut result = Vec::new();
+                for (i, name) in re.capture_names().enumerate().skip(1) {
+                    if let Some(m) = captures.get(i) {
+                        result.push((i.to_string(), String::from(m.as_str())));
+                        if let Some(name) = name {
+                            result.push((String::from(name), String::from(m.as_str())));
+                        }
+                    }
+                }
+                return result;
+            }
+        }
+    }
+    Vec::new()
+}
+
+/// Fill `$sni_1`-style references with the captures of sni regex before rewriting.
+fn expand_sni<'r>(replace: &'r str, ctx: &GatewayCTX) -> Cow<'r, str> {
+    if ctx.sni_captures.is_empty() || !replace.contains("$sni_") && !replace.contains("${sni_") {
+        return Cow::Borrowed(replace);
+    }
+    let mut captures = ctx.sni_captures.iter().collect::<Vec<&(String, String)>>();
+    // replace `$sni_10` before `$sni_1`
+    captures.sort_by_key(|c| std::cmp::Reverse(c.0.len()));
+    let mut result = String::from(replace);
+    for (name, value) in captures {
+        let value = value.replace('$', "$$");
+        result = result
+            .replace(&format!("${{{}}}", name), &value)
+            .replace(&format!("${}", name), &value);
+    }
+    Cow::Owned(result)
+}
+
 pub fn find_route_with_start<'a>(
     sni: &'a str,
     uri: &str,
-    routes: &'a HashMap<String, HashMap<String, Source>>,
+    req: &RequestHeader,
+    routes: &'a Routes,
     depth: usize,
     ctx: &mut GatewayCTX,
     starts_from: (&'a String, &'a Source),
 ) -> pingora::Result<((&'a String, &'a Source), String)> {
+    // captures of the path the location matched, a later `last` rewrite takes new ones
+    ctx.captures = match_captures(uri, starts_from.1);
     let mut uri = String::from(uri);
     let mut result: Option<pingora::Result<((&'a String, &'a Source), String)>> = None;
-    if let Some(rewrites) = &starts_from.1.rewrite_as_ref() {
+    if let Some(rewrites) = &starts_from.1.options().rewrite {
         for rewrite in rewrites {
             if result.is_some() {
                 break;
@@ -38,11 +227,15 @@ pub fn find_route_with_start<'a>(
             if rewrite.regex_as_ref().is_match(&uri) {
                 uri = rewrite
                     .regex_as_ref()
-                    .replace_all(&uri, rewrite.replace_as_ref())
+                    .replace_all(&uri, expand_sni(rewrite.replace_as_ref(), ctx).as_ref())
                     .to_string();
 
                 if rewrite.is_last() {
-                    result = Some(find_route(sni, &uri, routes, depth + 1, ctx));
+                    result = Some(find_route(sni, &uri, req, routes, depth + 1, ctx));
+                    break;
+                }
+                if let Some(status) = rewrite.redirect_status() {
+                    ctx.redirect = Some(status);
                     break;
                 }
             }
@@ -57,7 +250,8 @@ pub fn find_route_with_start<'a>(
 pub fn find_route<'a>(
     sni: &'a str,
     uri: &str,
-    routes: &'a HashMap<String, HashMap<String, Source>>,
+    req: &RequestHeader,
+    routes: &'a Routes,
     depth: usize,
     ctx: &mut GatewayCTX,
 ) -> pingora::Result<((&'a String, &'a Source), String)> {
@@ -65,27 +259,24 @@ pub fn find_route<'a>(
         Err(Error::new(HTTPStatus(502)))?;
     }
     let mut source: Option<(&String, &Source)> = None;
-    if let Some(sni_sources) = routes.get(&sni.to_lowercase()) {
-        ctx.sni = Some(String::from(sni));
-        for s in sni_sources {
-            if match_route(uri, s.1) {
+    for (key, captures) in routes.resolve(sni) {
+        for s in routes.get(key).unwrap() {
+            if match_route(uri, s.1)
+                && match_condition(s.1, req, ctx.client_ip)
+                && !has_condition(source)
+            {
                 source = Some(s);
             }
         }
-    };
-    if source.is_none() {
-        if let Some(sni_sources) = routes.get("") {
-            ctx.sni = Some(String::from(""));
-            for s in sni_sources {
-                if match_route(uri, s.1) {
-                    source = Some(s);
-                }
-            }
+        if source.is_some() {
+            ctx.sni = Some(key.clone());
+            ctx.sni_captures = captures;
+            break;
         }
     }
     match source {
         None => Err(Error::new(HTTPStatus(502)))?,
-        Some(s) => find_route_with_start(sni, uri, routes, depth, ctx, s),
+        Some(s) => find_route_with_start(sni, uri, req, routes, depth, ctx, s),
     }
 }
 
@@ -98,6 +289,9 @@ pub fn check_proxy_status(source: &Proxy) -> bool {
 
 pub fn check_static_status(source: &StaticServer, path: &str) -> bool {
     let path = path.split('?').collect::<Vec<&str>>()[0];
+    if path::has_parent(path) {
+        return false;
+    }
     let path = if path.ends_with('/') {
         format!("{}index.html", path)
     } else {
@@ -110,5 +304,82 @@ pub fn check_status(source: &Source, path: &str) -> bool {
     match source {
         Source::Proxy(proxy) => check_proxy_status(proxy),
         Source::Static(static_server) => check_static_status(static_server, path),
+        Source::Return(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_route, Routes};
+    use crate::config::{Source, SourceRaw};
+    use crate::gateway::GatewayCTX;
+    use pingora::http::RequestHeader;
+    use std::collections::HashMap;
+
+    fn source(config: &str) -> Source {
+        let raw: SourceRaw = toml::from_str(config).unwrap();
+        Source::from_raw(raw, "test.toml", false, 0).unwrap()
+    }
+
+    #[test]
+    fn captures_of_location_before_rewrite() {
+        let mut sources = HashMap::new();
+        sources.insert(
+            String::from("old"),
+            source(
+                r#"
+                source_type = "return"
+                status = 200
+                location = ['~ ^/old/(?<name>\w+)$']
+                rewrite = ['^/old/(.*)$ /renamed/$1 break']
+                "#,
+            ),
+        );
+        sources.insert(
+            String::from("moved"),
+            source(
+                r#"
+                source_type = "return"
+                status = 200
+                location = ['~ ^/moved/(\w+)$']
+                rewrite = ['^/moved/(.*)$ /new/$1 last']
+                "#,
+            ),
+        );
+        sources.insert(
+            String::from("new"),
+            source(
+                r#"
+                source_type = "return"
+                status = 200
+                location = ['~ ^/new/(\w)(\w*)$']
+                "#,
+            ),
+        );
+        let routes = Routes::new(HashMap::from([(String::new(), sources)])).unwrap();
+        let req = RequestHeader::build("GET", b"/", None).unwrap();
+
+        let mut ctx = GatewayCTX::new();
+        let (source, uri) = find_route("a", "/old/abc", &req, &routes, 0, &mut ctx).unwrap();
+        assert_eq!((source.0.as_str(), uri.as_str()), ("old", "/renamed/abc"));
+        assert_eq!(
+            ctx.captures,
+            vec![
+                (String::from("1"), String::from("abc")),
+                (String::from("name"), String::from("abc")),
+            ]
+        );
+
+        // the source reached by `last` matched the rewritten path
+        let mut ctx = GatewayCTX::new();
+        let (source, _) = find_route("a", "/moved/xyz", &req, &routes, 0, &mut ctx).unwrap();
+        assert_eq!(source.0, "new");
+        assert_eq!(
+            ctx.captures,
+            vec![
+                (String::from("1"), String::from("x")),
+                (String::from("2"), String::from("yz")),
+            ]
+        );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,95 @@
+use crate::config::SecureLink;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use urlencoding::{decode, encode};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, PartialEq)]
+pub enum LinkStatus {
+    Valid,
+    Invalid,
+    Expired,
+}
+
+fn mac(secret: &[u8], path: &str, expires: u64, ip: Option<IpAddr>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    if let Some(ip) = ip {
+        mac.update(b"\n");
+        mac.update(ip.to_string().as_bytes());
+    }
+    mac
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Signature of `path` (as in the url, without query) valid until `expires` (unix time).
+pub fn sign(secret: &[u8], path: &str, expires: u64, ip: Option<IpAddr>) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(mac(secret, path, expires, ip).finalize().into_bytes())
+}
+
+/// Append the expiry and the signature to `url`, valid for `ttl` seconds.
+pub fn signed_url(conf: &SecureLink, url: &str, ttl: u64, ip: Option<IpAddr>) -> String {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+    let expires = now() + ttl;
+    let signature = sign(&conf.secret, path, expires, ip);
+    format!(
+        "{}?{}{}={}&{}={}",
+        path,
+        query.map(|q| format!("{}&", q)).unwrap_or_default(),
+        encode(&conf.expires_param),
+        expires,
+        encode(&conf.signature_param),
+        signature
+    )
+}
+
+/// Check the signature in the query of `uri`, then the expiry.
+pub fn verify(conf: &SecureLink, uri: &str, ip: Option<IpAddr>) -> LinkStatus {
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let mut expires = None;
+    let mut signature = None;
+    for (k, v) in query.split('&').filter_map(|q| q.split_once('=')) {
+        let k = decode(k).map(|k| k.into_owned()).unwrap_or(String::from(k));
+        if k == conf.expires_param {
+            expires = v.parse::<u64>().ok();
+        } else if k == conf.signature_param {
+            signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(v.trim_end_matches('='))
+                .ok();
+        }
+    }
+    let (expires, signature) = match (expires, signature) {
+        (Some(expires), Some(signature)) => (expires, signature),
+        _ => return LinkStatus::Invalid,
+    };
+    let ip = if conf.bind_ip { ip } else { None };
+    if conf.bind_ip && ip.is_none() {
+        return LinkStatus::Invalid;
+    }
+    if mac(&conf.secret, path, expires, ip)
+        .verify_slice(&signature)
+        .is_err()
+    {
+        return LinkStatus::Invalid;
+    }
+    if expires < now() {
+        return LinkStatus::Expired;
+    }
+    LinkStatus::Valid
+}
@@ -0,0 +1,126 @@
+use crate::config::HeaderRule;
+use crate::gateway::GatewayCTX;
+use http::header;
+use pingora::prelude::Session;
+use std::env;
+
+/// Expand `$name`, `${name}` and `$1`-style references in `template`.
+///
+/// `lookup` resolves a reference name (`"host"`, `"1"`, ...); unknown references expand to an
+/// empty string. `$$` yields a literal `$`.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = r;
+            continue;
+        }
+        let (name, r) = if let Some(r) = rest.strip_prefix('{') {
+            match r.find('}') {
+                Some(end) => (&r[..end], &r[end + 1..]),
+                None => {
+                    result.push('$');
+                    continue;
+                }
+            }
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() {
+            result.push('$');
+            continue;
+        }
+        if let Some(value) = lookup(name) {
+            result.push_str(&value);
+        }
+        rest = r;
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Resolve a request variable, regex captures of location are referred by `1` or the name.
+pub fn request_variable(session: &Session, ctx: &GatewayCTX, name: &str) -> Option<String> {
+    let uri = &session.req_header().uri;
+    match name {
+        "host" => Some(host(session)),
+        "scheme" => Some(String::from(scheme(session))),
+        "request_uri" => Some(match &ctx.request_uri {
+            Some(request_uri) => request_uri.clone(),
+            None => uri.to_string(),
+        }),
+        "uri" => Some(String::from(uri.path())),
+        "args" | "query_string" => Some(String::from(uri.query().unwrap_or_default())),
+        "is_args" => Some(String::from(match uri.query() {
+            Some(_) => "?",
+            None => "",
+        })),
+        "request_method" => Some(session.req_header().method.to_string()),
+        "remote_addr" => ctx.client_ip.map(|ip| ip.to_string()),
+        "remote_user" => ctx.remote_user.clone(),
+        "request_id" => Some(ctx.request_id.clone()),
+        "upstream_addr" => ctx.upstream_addr.clone(),
+        "waf_tags" => Some(ctx.waf_tags.join(",")),
+        _ if name.starts_with("sni_") => ctx
+            .sni_captures
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone()),
+        _ if name.starts_with("env_") => env::var(&name[4..]).ok(),
+        _ => ctx
+            .captures
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone()),
+    }
+}
+
+/// Render the values of header rules with request variables, the ones without `template` are
+/// kept as is.
+pub fn render_headers<'a>(
+    headers: &'a [HeaderRule],
+    session: &Session,
+    ctx: &GatewayCTX,
+) -> Vec<(&'a HeaderRule, String)> {
+    headers
+        .iter()
+        .map(|rule| match rule.template {
+            true => (
+                rule,
+                render(&rule.value, |name| request_variable(session, ctx, name)),
+            ),
+            false => (rule, rule.value.clone()),
+        })
+        .collect()
+}
+
+/// `Host` of the request, without port.
+pub fn host(session: &Session) -> String {
+    let host = match session.get_header(header::HOST) {
+        None => "",
+        Some(host) => host.to_str().unwrap_or_default(),
+    };
+    match host.rsplit_once(':') {
+        Some((h, port)) if !port.contains(']') => String::from(h),
+        _ => String::from(host),
+    }
+}
+
+pub fn scheme(session: &Session) -> &'static str {
+    match session.digest() {
+        Some(digest) if digest.ssl_digest.is_some() => "https",
+        _ => "http",
+    }
+}
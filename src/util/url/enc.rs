@@ -1,5 +1,7 @@
+use http::Uri;
 use std::borrow::Cow;
 use std::str;
+use urlencoding::decode;
 
 // /// Wrapper type that implements `Display`. Encodes on the fly, without allocating.
 // /// Percent-encodes every byte except alphanumerics and `-`, `_`, `.`, `~`, `/`, `?`, `&`. Assumes UTF-8 encoding.
@@ -19,20 +21,47 @@ use std::str;
 //     }
 // }
 
-/// Percent-encodes every byte except alphanumerics and `-`, `_`, `.`, `~`, `/`, `?`, `&`. Assumes UTF-8 encoding.
+/// Percent-decoded path of `uri` followed by its query as is, which locations and rewrites see.
 ///
-/// Call `.into_owned()` if you need a `String`
-#[inline(always)]
-pub fn encode_ignore_slash(data: &'_ str) -> Cow<'_, str> {
-    encode_binary(data.as_bytes())
+/// The raw path is kept if it does not decode to UTF-8.
+pub fn decode_path(uri: &Uri) -> String {
+    let path = uri.path();
+    let path = decode(path).unwrap_or(Cow::Borrowed(path));
+    match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.into_owned(),
+    }
+}
+
+/// Percent-encodes what is not allowed in a URI, the reverse of `decode_path`.
+///
+/// `%` is encoded in the path, which was decoded, but kept in the query.
+pub fn encode_uri(uri: &str) -> String {
+    match uri.split_once('?') {
+        Some((path, query)) => format!(
+            "{}?{}",
+            encode_binary(path.as_bytes(), is_path_char),
+            encode_binary(query.as_bytes(), is_query_char)
+        ),
+        None => encode_binary(uri.as_bytes(), is_path_char).into_owned(),
+    }
+}
+
+fn is_path_char(c: u8) -> bool {
+    matches!(c, b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'-' | b'.' | b'_' | b'~' | b'/'
+        | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@')
 }
 
-/// Percent-encodes every byte except alphanumerics and `-`, `_`, `.`, `~`, `/`, `?`, `&`.
+fn is_query_char(c: u8) -> bool {
+    is_path_char(c) || c == b'?' || c == b'%'
+}
+
+/// Percent-encodes every byte `safe` rejects.
 #[inline]
-fn encode_binary(data: &'_ [u8]) -> Cow<'_, str> {
+fn encode_binary(data: &'_ [u8], safe: fn(u8) -> bool) -> Cow<'_, str> {
     // add maybe extra capacity, but try not to exceed allocator's bucket size
     let mut escaped = String::with_capacity(data.len() | 15);
-    let unmodified = append_string(data, &mut escaped, true);
+    let unmodified = append_string(data, &mut escaped, true, safe);
     if unmodified {
         return Cow::Borrowed(unsafe {
             // encode_into has checked it is ASCII
@@ -42,8 +71,8 @@ fn encode_binary(data: &'_ [u8]) -> Cow<'_, str> {
     Cow::Owned(escaped)
 }
 
-fn append_string(data: &[u8], escaped: &mut String, may_skip: bool) -> bool {
-    encode_into(data, may_skip, |s| {
+fn append_string(data: &[u8], escaped: &mut String, may_skip: bool, safe: fn(u8) -> bool) -> bool {
+    encode_into(data, may_skip, safe, |s| {
         escaped.push_str(s);
         Ok::<_, std::convert::Infallible>(())
     })
@@ -53,13 +82,13 @@ fn append_string(data: &[u8], escaped: &mut String, may_skip: bool) -> bool {
 fn encode_into<E>(
     mut data: &[u8],
     may_skip_write: bool,
+    safe: fn(u8) -> bool,
     mut push_str: impl FnMut(&str) -> Result<(), E>,
 ) -> Result<bool, E> {
     let mut pushed = false;
     loop {
         // Fast path to skip over safe chars at the beginning of the remaining string
-        let ascii_len = data.iter()
-            .take_while(|&&c| matches!(c, b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' |  b'-' | b'.' | b'_' | b'~' | b'/' | b'?' | b'&')).count();
+        let ascii_len = data.iter().take_while(|&&c| safe(c)).count();
 
         let (safe, rest) = if ascii_len >= data.len() {
             if !pushed && may_skip_write {
@@ -97,3 +126,16 @@ fn to_hex_digit(digit: u8) -> u8 {
         b'A' - 10 + digit
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let uri = decode_path(&"/a%20b/%E4%B8%AD%2520?q=a%20b&c=%26".parse().unwrap());
+        assert_eq!(uri, "/a b/中%20?q=a%20b&c=%26");
+        assert_eq!(encode_uri(&uri), "/a%20b/%E4%B8%AD%2520?q=a%20b&c=%26");
+        assert_eq!(encode_uri("/c d/$1?x=a b"), "/c%20d/$1?x=a%20b");
+    }
+}
@@ -0,0 +1,214 @@
+use crate::config::{parse_waf_rules, WafRule, WafRulesRaw, WafTarget, DEFAULT_RULES};
+use anyhow::anyhow;
+use http::header;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use pingora::http::RequestHeader;
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+static DEFAULTS: Lazy<Vec<WafRule>> = Lazy::new(|| {
+    let raw: WafRulesRaw = toml::from_str(DEFAULT_RULES).unwrap();
+    parse_waf_rules(raw, "waf_rules.toml").unwrap()
+});
+
+/// Rules of a file or the config, followed by the default ones if enabled.
+pub struct RuleFile {
+    /// `None` for rules written in the config
+    path: Option<String>,
+    default_rules: bool,
+    state: RwLock<(Option<SystemTime>, Arc<Vec<WafRule>>)>,
+}
+
+impl Debug for RuleFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RuleFile")
+            .field("path", &self.path)
+            .field("default_rules", &self.default_rules)
+            .finish()
+    }
+}
+
+impl RuleFile {
+    pub fn load(path: String, default_rules: bool) -> anyhow::Result<Self> {
+        let modified = modified(&path);
+        let rules = with_defaults(parse(&path)?, default_rules);
+        Ok(Self {
+            path: Some(path),
+            default_rules,
+            state: RwLock::new((modified, Arc::new(rules))),
+        })
+    }
+
+    pub fn fixed(rules: Vec<WafRule>, default_rules: bool) -> Self {
+        Self {
+            path: None,
+            default_rules,
+            state: RwLock::new((None, Arc::new(with_defaults(rules, default_rules)))),
+        }
+    }
+
+    fn reload(&self, path: &str) {
+        let modified = modified(path);
+        if self.state.read().unwrap().0 == modified {
+            return;
+        }
+        match parse(path) {
+            Ok(rules) => {
+                info!("Reloaded waf rule file {}", path);
+                *self.state.write().unwrap() =
+                    (modified, Arc::new(with_defaults(rules, self.default_rules)));
+            }
+            Err(e) => {
+                error!("Failed to reload waf rule file, keep the old one: {}", e);
+                self.state.write().unwrap().0 = modified;
+            }
+        }
+    }
+
+    pub fn rules(&self) -> Arc<Vec<WafRule>> {
+        if let Some(path) = &self.path {
+            self.reload(path);
+        }
+        self.state.read().unwrap().1.clone()
+    }
+}
+
+fn with_defaults(mut rules: Vec<WafRule>, default_rules: bool) -> Vec<WafRule> {
+    if default_rules {
+        rules.extend(DEFAULTS.iter().cloned());
+    }
+    rules
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn parse(path: &str) -> anyhow::Result<Vec<WafRule>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("{} Failed to read waf rule file: {}", path, e))?;
+    let raw: WafRulesRaw = toml::from_str(&content)
+        .map_err(|e| anyhow!("Failed to parse {}: {}", path, e.message()))?;
+    parse_waf_rules(raw, path)
+}
+
+fn decode(value: &str) -> String {
+    String::from_utf8_lossy(&urlencoding::decode_binary(value.as_bytes())).into_owned()
+}
+
+fn decode_form(value: &str) -> String {
+    decode(&value.replace('+', " "))
+}
+
+/// Values of the request header a rule can match, the body is matched in `body_target`.
+pub fn header_target<'a>(req: &'a RequestHeader, target: &WafTarget) -> Vec<Cow<'a, str>> {
+    match target {
+        WafTarget::Method => vec![Cow::Borrowed(req.method.as_str())],
+        WafTarget::Path => vec![Cow::Owned(decode(req.uri.path()))],
+        WafTarget::Query => match req.uri.query() {
+            Some(query) => vec![Cow::Owned(decode_form(query))],
+            None => Vec::new(),
+        },
+        WafTarget::UserAgent => header_values(req, &header::USER_AGENT),
+        WafTarget::Header(name) => header_values(req, name),
+        WafTarget::Body => Vec::new(),
+    }
+}
+
+fn header_values<'a>(req: &'a RequestHeader, name: &header::HeaderName) -> Vec<Cow<'a, str>> {
+    req.headers
+        .get_all(name)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()))
+        .collect()
+}
+
+/// The inspected part of the body, form bodies are decoded like queries.
+pub fn body_target(req: &RequestHeader, body: &[u8]) -> String {
+    let body = String::from_utf8_lossy(body);
+    let form = req
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if form {
+        decode_form(&body)
+    } else {
+        body.into_owned()
+    }
+}
+
+/// Whether any rule looks at the body, so it has to be held before proxying.
+pub fn inspects_body(rules: &[WafRule]) -> bool {
+    rules
+        .iter()
+        .any(|rule| rule.targets.contains(&WafTarget::Body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Id of the first default rule matching the request.
+    fn matched(uri: &str, user_agent: &str) -> Option<&'static str> {
+        let mut req = RequestHeader::build("GET", uri.as_bytes(), None).unwrap();
+        req.insert_header(header::USER_AGENT, user_agent).unwrap();
+        DEFAULTS
+            .iter()
+            .find(|rule| {
+                rule.targets.iter().any(|target| {
+                    header_target(&req, target)
+                        .iter()
+                        .any(|value| rule.is_match(value))
+                })
+            })
+            .map(|rule| rule.id.as_str())
+    }
+
+    #[test]
+    fn default_rules_catch_probes() {
+        let cases = [
+            ("/item?id=1%27%20OR%20%271%27=%271", "sqli-tautology"),
+            (
+                "/item?id=1+UNION+ALL+SELECT+password+FROM+users",
+                "sqli-union-select",
+            ),
+            ("/item?id=1;DROP%20TABLE%20users", "sqli-stacked-query"),
+            ("/item?id=1+AND+SLEEP(5)", "sqli-time-based"),
+            (
+                "/search?q=%3CScript%3Ealert(1)%3C/script%3E",
+                "xss-script-tag",
+            ),
+            (
+                "/search?q=%3Cimg%20src=x%20onerror=alert(1)%3E",
+                "xss-event-handler",
+            ),
+            ("/go?to=javascript:alert(1)", "xss-javascript-uri"),
+            ("/static/..%2f..%2fetc/passwd", "path-traversal"),
+            ("/download?file=../../secret", "path-traversal"),
+            ("/download?file=/etc/passwd", "path-traversal-files"),
+        ];
+        for (uri, id) in cases {
+            assert_eq!(matched(uri, "curl/8.0"), Some(id), "{}", uri);
+        }
+        assert_eq!(matched("/", "sqlmap/1.7"), Some("scanner-user-agent"));
+    }
+
+    #[test]
+    fn default_rules_pass_ordinary_requests() {
+        let uris = [
+            "/",
+            "/search?q=select+a+union+or+a+join",
+            "/posts?title=it%27s+or+not&page=2",
+            "/docs/v1.2/..hidden/file.txt",
+            "/events?on=monday&script=build",
+        ];
+        for uri in uris {
+            assert_eq!(matched(uri, "Mozilla/5.0"), None, "{}", uri);
+        }
+    }
+}